@@ -0,0 +1,98 @@
+//! Scanner throughput on a generated multi-megabyte script.
+//!
+//! Run with `cargo bench --features bench --bench scanner`. The current
+//! scanner is timed against the `Vec<char>` one it replaced, kept in
+//! `vec_char.rs`, on the same input.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rlox::{Scanner, TokenType};
+
+mod vec_char;
+
+const TARGET_BYTES: usize = 8 * 1024 * 1024;
+const RUNS: usize = 5;
+
+fn generate_source() -> String {
+    let mut source = String::with_capacity(TARGET_BYTES + 1024);
+    let mut idx = 0;
+    while source.len() < TARGET_BYTES {
+        source += &format!(
+            "// Function number {idx}, with a comment to skip.\n\
+             fun compute_{idx}(a, b) {{\n\
+             \tvar total = a * {idx}.5 + b;\n\
+             \tif (total >= 100 and !(b == nil)) {{ print \"big: naïve \" + \"{idx}\"; }}\n\
+             \tfor (var i = 0; i < 10; i = i + 1) {{ total = total - i / 2; }}\n\
+             \treturn total;\n\
+             }}\n\n"
+        );
+        idx += 1;
+    }
+    source
+}
+
+fn best_of<F: FnMut() -> usize>(mut run: F) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut count = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        count = black_box(run());
+        best = best.min(start.elapsed());
+    }
+    (best, count)
+}
+
+fn report(label: &str, bytes: usize, (elapsed, count): (Duration, usize), unit: &str) {
+    let seconds = elapsed.as_secs_f64();
+    println!(
+        "{:<12} {:>8.1} MB/s {:>12.0} {}/s  ({} {} in {:.2?})",
+        label,
+        bytes as f64 / seconds / 1e6,
+        count as f64 / seconds,
+        unit,
+        count,
+        unit,
+        elapsed,
+    );
+}
+
+fn main() {
+    let source = generate_source();
+    println!("input: {:.1} MB, best of {} runs", source.len() as f64 / 1e6, RUNS);
+
+    let old = best_of(|| {
+        let mut scanner = vec_char::Scanner::init(black_box(&source));
+        let mut count = 0;
+        loop {
+            let token = scanner.scan_token();
+            match token.ttype() {
+                vec_char::TokenType::EOF => break,
+                vec_char::TokenType::Error => panic!("Unexpected error token: {}", token.as_str()),
+                _ => count += 1,
+            }
+            black_box(token.as_str());
+        }
+        count
+    });
+    report("Vec<char>", source.len(), old, "tokens");
+
+    let tokens = best_of(|| {
+        let mut scanner = Scanner::init(black_box(&source));
+        let mut count = 0;
+        loop {
+            let token = scanner.scan_token();
+            match token.ttype() {
+                TokenType::EOF => break,
+                TokenType::Error => panic!("Unexpected error token: {}", token.as_str()),
+                _ => count += 1,
+            }
+            black_box(token.as_str());
+        }
+        count
+    });
+    report("borrowed", source.len(), tokens, "tokens");
+
+    assert_eq!(old.1, tokens.1, "The scanners disagree on the token count");
+    println!("speedup: {:.2}x", old.0.as_secs_f64() / tokens.0.as_secs_f64());
+}
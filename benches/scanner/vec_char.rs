@@ -0,0 +1,344 @@
+//! The scanner as it was before it borrowed the source: it copies the
+//! input into a `Vec<char>` and allocates a `String` per token. It is kept
+//! unchanged apart from dropping what the benchmark does not call, so the
+//! speedup can be measured against it.
+#![allow(dead_code)]
+
+pub struct Scanner {
+    buffer: Vec<char>,
+    start: usize,
+    current: usize,
+    line: usize,
+    column: usize,
+    start_line: usize,
+    start_column: usize,
+}
+
+impl Scanner {
+
+    pub fn init(source: &str) -> Scanner {
+        Scanner { 
+            buffer: source.chars().collect(),
+            start: 0,
+            current: 0,
+            line: 1,
+            column: 1,
+            start_line: 1,
+            start_column: 1,
+        }
+    }
+
+    pub fn scan_token(&mut self) -> Token {
+        self.skip_whitespace();
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.column;
+        if self.is_at_end() {
+            return self.make_token(TokenType::EOF);
+        }
+        let c = self.advance();
+        match c {
+            '(' => self.make_token(TokenType::LeftParen),
+            ')' => self.make_token(TokenType::RightParen),
+            '{' => self.make_token(TokenType::LeftBrace),
+            '}' => self.make_token(TokenType::RightBrace),
+            ';' => self.make_token(TokenType::SemiColon),
+            ',' => self.make_token(TokenType::Comma),
+            '.' => self.make_token(TokenType::Dot),
+            '-' => self.make_token(TokenType::Minus),
+            '+' => self.make_token(TokenType::Plus),
+            '/' => self.make_token(TokenType::Slash),
+            '*' => self.make_token(TokenType::Star),
+            '!' => {
+                if self.match_char('=') {
+                    self.make_token(TokenType::BangEqual)
+                } else {
+                    self.make_token(TokenType::Bang)
+                }
+            },
+            '=' => {
+                if self.match_char('=') {
+                    self.make_token(TokenType::EqualEqual)
+                } else {
+                    self.make_token(TokenType::Equal)
+                }
+            },
+            '<' => {
+                if self.match_char('=') {
+                    self.make_token(TokenType::LessEqual)
+                } else {
+                    self.make_token(TokenType::Less)
+                }
+            }
+            '>' => {
+                if self.match_char('=') {
+                    self.make_token(TokenType::GreateEqual)
+                } else {
+                    self.make_token(TokenType::Greater)
+                }
+            }
+            '"' => self.string(),
+            _ => {
+                if c.is_ascii_digit() {
+                    return self.number();
+                }
+                if Self::identifier_first(c) {
+                    return self.identifier();
+                }
+                self.error_token("Unexpected character.")
+            },
+        }
+    }
+
+    fn identifier_first(c: char) -> bool {
+        c.is_alphabetic() || c == '_'
+    }
+
+    fn identifier_rest(c: char) -> bool {
+        c.is_alphanumeric() || c == '_'
+    }
+
+    fn identifier(&mut self) -> Token {
+        while Self::identifier_rest(self.peek()) {
+            let _ = self.advance();
+        }
+        let tt = self.identifier_type();
+        self.make_token(tt)
+    }
+
+    fn identifier_type(&mut self) -> TokenType {
+        match self.buffer[self.start] {
+            'a' => self.check_keyword(1, 2, vec!['n', 'd'], TokenType::And),
+            'c' => self.check_keyword(1, 4, vec!['l', 'a', 's', 's'], TokenType::Class),
+            'e' => self.check_keyword(1, 3, vec!['l', 's', 'e'], TokenType::Else),
+            'f' => {
+                if self.current - self.start > 1 {
+                    match self.buffer[self.start + 1] {
+                        'a' => self.check_keyword(2, 3, vec!['l', 's', 'e'], TokenType::False),
+                        'o' => self.check_keyword(2, 1, vec!['r'], TokenType::For),
+                        'u' => self.check_keyword(2, 1, vec!['n'], TokenType::Fun),
+                        _ => TokenType::Identifier
+                    }
+                } else {
+                    TokenType::Identifier
+                }
+            }
+            'i' => self.check_keyword(1, 1, vec!['f'], TokenType::If),
+            'n' => self.check_keyword(1, 2, vec!['i', 'l'], TokenType::Nil),
+            'o' => self.check_keyword(1, 1, vec!['r'], TokenType::Or),
+            'p' => self.check_keyword(1, 4, vec!['r', 'i', 'n', 't'], TokenType::Print),
+            'r' => self.check_keyword(1, 5, vec!['e', 't', 'u', 'r', 'n'], TokenType::Return),
+            's' => self.check_keyword(1, 4, vec!['u', 'p', 'e', 'r'], TokenType::Super),
+            't' => {
+                if self.current - self.start > 1 {
+                    match self.buffer[self.start + 1] {
+                        'h' => self.check_keyword(2, 2, vec!['i', 's'], TokenType::This),
+                        'r' => self.check_keyword(2, 2, vec!['u', 'e'], TokenType::True),
+                        _ => TokenType::Identifier
+                    }
+                } else {
+                    TokenType::Identifier
+                }
+            }
+            'v' => self.check_keyword(1 ,2, vec!['a', 'r'], TokenType::Var),
+            'w' => self.check_keyword(1, 4, vec!['h', 'i', 'l', 'e'], TokenType::While),
+            _ => TokenType::Identifier,
+        }
+    }
+
+    fn check_keyword(&mut self, offset: usize, length: usize, chars: Vec<char>, ttype: TokenType) -> TokenType {
+        if self.current - self.start != offset + length {
+            return TokenType::Identifier
+        }
+        for (idx, c) in chars.iter().enumerate().take(length) {
+            if *c != self.buffer[self.start + offset + idx] {
+                return TokenType::Identifier
+            }
+        }
+        ttype
+    }
+
+    fn number(&mut self) -> Token {
+        while self.peek().is_ascii_digit() {
+            let _ = self.advance();
+        }
+        if self.peek() == '.' && self.peek_next(1).is_ascii_digit() {
+            let _ = self.advance();
+            while self.peek().is_ascii_digit() {
+                let _ = self.advance();
+            }    
+        }
+        self.make_token(TokenType::Number)
+    }
+
+    fn string(&mut self) -> Token {
+        while !self.is_at_end() && self.peek() != '"' {
+            let _ = self.advance();
+        }
+        if self.is_at_end() {
+            return self.error_token("Unterminated string.");
+        }
+        let _ = self.advance();
+        self.make_token(TokenType::String)
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            if self.peek().is_whitespace() {
+                let _ = self.advance();
+            } else if self.peek() == '/' {
+                if self.peek_next(1) == '/' {
+                    while self.peek() != '\n' && !self.is_at_end() {
+                        let _ = self.advance();
+                    }
+                } else {
+                    return;
+                }
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn peek(&self) -> char {
+        if self.is_at_end() {
+            '\0'
+        } else {
+            self.buffer[self.current]
+        }
+    }
+
+    fn peek_next(&self, steps: usize) -> char {
+        if self.current + steps >= self.buffer.len() {
+            '\0'
+        } else {
+            self.buffer[self.current + steps]
+        }
+    }
+
+    fn match_char(&mut self, the_char: char) -> bool {
+        if self.peek() != the_char {
+            return false;
+        }
+        let _ = self.advance();
+        true
+    }
+
+    pub fn advance(&mut self) -> char {
+        let c = self.buffer[self.current];
+        match c {
+            '\n' => {
+                self.line += 1;
+                self.column = 1;
+            },
+            _ => {
+                self.column += 1;
+            }
+        }
+        self.current += 1;
+        c
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.current >= self.buffer.len()
+    }
+
+    fn make_token(&self, ttype: TokenType) -> Token {
+        let mut s = String::new();
+        for i in self.start..self.current {
+            s.push(self.buffer[i]);
+        }
+        Token {
+            token_type: ttype,
+            the_string: s,
+            line: self.start_line,
+            column: self.start_column,
+            start: self.start,
+            length: self.current - self.start,
+        }
+    }
+
+    fn error_token(&self, message: &str) -> Token {
+        Token {
+            token_type: TokenType::Error,
+            the_string: message.to_string(),
+            line: self.start_line,
+            column: self.start_column,
+            start: self.start,
+            length: self.current - self.start,
+        }
+    }
+}
+
+/// A lexeme of the source. `line` and `column` locate its first character
+/// (columns count characters, so a tab is one column), and `start` and
+/// `length` give its span as character offsets into the source.
+#[derive(Clone)]
+pub struct Token {
+    token_type: TokenType,
+    the_string: String,
+    line: usize,
+    column: usize,
+    start: usize,
+    length: usize,
+}
+
+impl Token {
+    pub fn ttype(&self) -> TokenType {
+        self.token_type
+    }
+    pub fn as_str(&self) -> &str {
+        &self.the_string
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TokenType {
+    // Single character tokens.
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    Comma,
+    Dot,
+    Minus,
+    Plus,
+    SemiColon,
+    Slash,
+    Star,
+    // One or two character tokens.
+    Bang,
+    BangEqual,
+    Equal,
+    EqualEqual,
+    Greater,
+    GreateEqual,
+    Less,
+    LessEqual,
+    // Literals.
+    Identifier,
+    String,
+    Number,
+    // Keywords.
+    And,
+    Class,
+    Else,
+    False,
+    For,
+    Fun,
+    If,
+    Nil,
+    Or,
+    Print,
+    Return,
+    Super,
+    This,
+    True,
+    Var,
+    While,
+    // Special.
+    Error,
+    #[allow(clippy::upper_case_acronyms)]
+    EOF,
+    Dummy,
+}
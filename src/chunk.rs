@@ -1,5 +1,3 @@
-use std::usize;
-
 use crate::values::Value;
 
 #[derive(PartialEq, Clone, Copy, Debug)]
@@ -11,6 +9,7 @@ impl std::fmt::Display for Byte {
     }
 }
 
+#[allow(clippy::enum_variant_names)]
 pub enum OpCode {
     OpConstant,
     OpConstantLong,
@@ -26,6 +25,30 @@ pub enum OpCode {
     OpEqual,
     OpGreater,
     OpLess,
+    OpPrint,
+    OpPop,
+    OpPopN,
+    OpGetLocal,
+    OpSetLocal,
+    OpDefineGlobal,
+    OpGetGlobal,
+    OpSetGlobal,
+    OpJump,
+    OpJumpIfFalse,
+    OpLoop,
+    OpCall,
+    OpClosure,
+    OpGetUpvalue,
+    OpSetUpvalue,
+    OpCloseUpvalue,
+    OpClass,
+    OpGetProperty,
+    OpSetProperty,
+    OpMethod,
+    OpInvoke,
+    OpInherit,
+    OpGetSuper,
+    OpSuperInvoke,
     OpReturn,
 }
 
@@ -66,6 +89,30 @@ impl TryFrom<Byte> for OpCode {
         const OP_EQUAL_BYTE: Byte = Byte(OpCode::OpEqual as u8);
         const OP_GREATER_BYTE: Byte = Byte(OpCode::OpGreater as u8);
         const OP_LESS_BYTE: Byte = Byte(OpCode::OpLess as u8);
+        const OP_PRINT_BYTE: Byte = Byte(OpCode::OpPrint as u8);
+        const OP_POP_BYTE: Byte = Byte(OpCode::OpPop as u8);
+        const OP_POP_N_BYTE: Byte = Byte(OpCode::OpPopN as u8);
+        const OP_GET_LOCAL_BYTE: Byte = Byte(OpCode::OpGetLocal as u8);
+        const OP_SET_LOCAL_BYTE: Byte = Byte(OpCode::OpSetLocal as u8);
+        const OP_DEFINE_GLOBAL_BYTE: Byte = Byte(OpCode::OpDefineGlobal as u8);
+        const OP_GET_GLOBAL_BYTE: Byte = Byte(OpCode::OpGetGlobal as u8);
+        const OP_SET_GLOBAL_BYTE: Byte = Byte(OpCode::OpSetGlobal as u8);
+        const OP_JUMP_BYTE: Byte = Byte(OpCode::OpJump as u8);
+        const OP_JUMP_IF_FALSE_BYTE: Byte = Byte(OpCode::OpJumpIfFalse as u8);
+        const OP_LOOP_BYTE: Byte = Byte(OpCode::OpLoop as u8);
+        const OP_CALL_BYTE: Byte = Byte(OpCode::OpCall as u8);
+        const OP_CLOSURE_BYTE: Byte = Byte(OpCode::OpClosure as u8);
+        const OP_GET_UPVALUE_BYTE: Byte = Byte(OpCode::OpGetUpvalue as u8);
+        const OP_SET_UPVALUE_BYTE: Byte = Byte(OpCode::OpSetUpvalue as u8);
+        const OP_CLOSE_UPVALUE_BYTE: Byte = Byte(OpCode::OpCloseUpvalue as u8);
+        const OP_CLASS_BYTE: Byte = Byte(OpCode::OpClass as u8);
+        const OP_GET_PROPERTY_BYTE: Byte = Byte(OpCode::OpGetProperty as u8);
+        const OP_SET_PROPERTY_BYTE: Byte = Byte(OpCode::OpSetProperty as u8);
+        const OP_METHOD_BYTE: Byte = Byte(OpCode::OpMethod as u8);
+        const OP_INVOKE_BYTE: Byte = Byte(OpCode::OpInvoke as u8);
+        const OP_INHERIT_BYTE: Byte = Byte(OpCode::OpInherit as u8);
+        const OP_GET_SUPER_BYTE: Byte = Byte(OpCode::OpGetSuper as u8);
+        const OP_SUPER_INVOKE_BYTE: Byte = Byte(OpCode::OpSuperInvoke as u8);
         match byte {
             OP_CONSTANT_BYTE => Ok(OpCode::OpConstant),
             OP_CONSTANT_LONG_BYTE => Ok(OpCode::OpConstantLong),
@@ -82,6 +129,30 @@ impl TryFrom<Byte> for OpCode {
             OP_EQUAL_BYTE => Ok(OpCode::OpEqual),
             OP_GREATER_BYTE => Ok(OpCode::OpGreater),
             OP_LESS_BYTE => Ok(OpCode::OpLess),
+            OP_PRINT_BYTE => Ok(OpCode::OpPrint),
+            OP_POP_BYTE => Ok(OpCode::OpPop),
+            OP_POP_N_BYTE => Ok(OpCode::OpPopN),
+            OP_GET_LOCAL_BYTE => Ok(OpCode::OpGetLocal),
+            OP_SET_LOCAL_BYTE => Ok(OpCode::OpSetLocal),
+            OP_DEFINE_GLOBAL_BYTE => Ok(OpCode::OpDefineGlobal),
+            OP_GET_GLOBAL_BYTE => Ok(OpCode::OpGetGlobal),
+            OP_SET_GLOBAL_BYTE => Ok(OpCode::OpSetGlobal),
+            OP_JUMP_BYTE => Ok(OpCode::OpJump),
+            OP_JUMP_IF_FALSE_BYTE => Ok(OpCode::OpJumpIfFalse),
+            OP_LOOP_BYTE => Ok(OpCode::OpLoop),
+            OP_CALL_BYTE => Ok(OpCode::OpCall),
+            OP_CLOSURE_BYTE => Ok(OpCode::OpClosure),
+            OP_GET_UPVALUE_BYTE => Ok(OpCode::OpGetUpvalue),
+            OP_SET_UPVALUE_BYTE => Ok(OpCode::OpSetUpvalue),
+            OP_CLOSE_UPVALUE_BYTE => Ok(OpCode::OpCloseUpvalue),
+            OP_CLASS_BYTE => Ok(OpCode::OpClass),
+            OP_GET_PROPERTY_BYTE => Ok(OpCode::OpGetProperty),
+            OP_SET_PROPERTY_BYTE => Ok(OpCode::OpSetProperty),
+            OP_METHOD_BYTE => Ok(OpCode::OpMethod),
+            OP_INVOKE_BYTE => Ok(OpCode::OpInvoke),
+            OP_INHERIT_BYTE => Ok(OpCode::OpInherit),
+            OP_GET_SUPER_BYTE => Ok(OpCode::OpGetSuper),
+            OP_SUPER_INVOKE_BYTE => Ok(OpCode::OpSuperInvoke),
             _ => Err(()),
         }
     }
@@ -124,13 +195,15 @@ impl Chunk {
             self.write_code(Byte::from(idx & 0xFF), line);
         }
     }
-    #[cfg(any(feature = "dumpChunk"))]
     pub fn code_size(&self) -> usize {
         self.code.len()
     }
     pub fn read_code(&self, idx: usize) -> Byte {
         self.code[idx]
     }
+    pub fn patch_code(&mut self, idx: usize, byte: Byte) {
+        self.code[idx] = byte;
+    }
     pub fn read_line(&self, idx: usize) -> usize {
         for (line, code_idx) in &self.lines {
             if idx < *code_idx {
@@ -139,11 +212,19 @@ impl Chunk {
         }
         0
     }
-    fn add_constant(&mut self, value: Value) -> usize {
+    pub fn add_constant(&mut self, value: Value) -> usize {
         self.values.push(value);
         self.values.len() - 1
     }
     pub fn read_constant(&self, idx: usize) -> Value {
         self.values[idx]
     }
+    pub fn constants(&self) -> &[Value] {
+        &self.values
+    }
+    pub fn byte_size(&self) -> usize {
+        self.code.capacity() * std::mem::size_of::<Byte>()
+            + self.values.capacity() * std::mem::size_of::<Value>()
+            + self.lines.capacity() * std::mem::size_of::<(usize, usize)>()
+    }
 }
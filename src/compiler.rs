@@ -1,80 +1,582 @@
 use crate::chunk::Byte;
 use crate::chunk::OpCode;
 use crate::chunk::Chunk;
+use crate::errors::CompileError;
+use crate::objects::ObjFunction;
+use crate::objects::ObjRef;
 use crate::scanner::Scanner;
 use crate::scanner::Token;
 use crate::scanner::TokenType;
 use crate::values::Value;
-use crate::virtual_machine::InterpretError;
-#[cfg(any(feature = "dumpChunk"))]
+use crate::virtual_machine::VM;
+#[cfg(feature = "dumpChunk")]
 use crate::disassembler;
 
-pub struct Parser {
-    current: Token,
-    previous: Token,
-    had_error: bool,
+const MAX_LOCALS: usize = 256;
+const MAX_UPVALUES: usize = 256;
+
+struct Local<'src> {
+    name: Token<'src>,
+    depth: Option<usize>,
+    is_captured: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Upvalue {
+    index: usize,
+    is_local: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    Function,
+    Initializer,
+    Method,
+    Script,
+}
+
+struct Compiler<'src> {
+    kind: FunctionType,
+    name: Option<ObjRef>,
+    arity: usize,
+    locals: Vec<Local<'src>>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+}
+
+impl<'src> Compiler<'src> {
+    fn new(kind: FunctionType, name: Option<ObjRef>) -> Compiler<'src> {
+        // Slot zero holds the function being called, or the receiver in methods.
+        let reserved_name = match kind {
+            FunctionType::Method | FunctionType::Initializer => Token::create_synthetic("this"),
+            _ => Token::create_dummy(),
+        };
+        let reserved = Local { name: reserved_name, depth: Some(0), is_captured: false };
+        Compiler {
+            kind,
+            name,
+            arity: 0,
+            locals: vec![reserved],
+            upvalues: vec![],
+            scope_depth: 0,
+        }
+    }
+}
+
+struct ClassCompiler {
+    has_superclass: bool,
+}
+
+pub struct Parser<'src> {
+    current: Token<'src>,
+    previous: Token<'src>,
     panic_mode: bool,
+    errors: Vec<CompileError>,
+    source_name: String,
+    source: &'src str,
+    compilers: Vec<Compiler<'src>>,
+    classes: Vec<ClassCompiler>,
 }
 
-impl Parser {
-    pub fn new() -> Parser {
+impl<'src> Parser<'src> {
+    pub fn new() -> Parser<'src> {
         Parser {
             current: Token::create_dummy(),
             previous: Token::create_dummy(),
-            had_error: false,
             panic_mode: false,
+            errors: vec![],
+            source_name: String::new(),
+            source: "",
+            compilers: vec![],
+            classes: vec![],
         }
     }
-    pub fn had_error(&self) -> bool {
-        self.had_error
-    }
-    pub fn set_error(&mut self, value: bool) {
-        self.had_error = value;
-    }
-    pub fn compile(&mut self, source: &str, chunk: &mut Chunk) -> Result<(), InterpretError> {
+    pub fn compile(&mut self, source: &'src str, vm: &mut VM) -> Result<ObjRef, Vec<CompileError>> {
+        self.source_name = vm.source_name().to_string();
+        self.source = source;
         let mut scanner = Scanner::init(source);
+        let mut chunk = Chunk::new();
+        self.compilers.push(Compiler::new(FunctionType::Script, None));
         self.advance(&mut scanner);
-        self.expression(chunk, &mut scanner);
-        self.consume(&mut scanner, TokenType::EOF, "Expect end of expression");
-        self.end_compiler(chunk);
-        #[cfg(any(feature = "dumpChunk"))]
-        if !self.had_error() {
-            disassembler::dump_chunk(chunk, "code");
-        }
-        if self.had_error() {
-            Err(InterpretError::CompileError)
+        while !self.match_token(&mut scanner, TokenType::EOF) {
+            self.declaration(&mut chunk, &mut scanner, vm);
+        }
+        let (function, _) = self.end_compiler(chunk, vm);
+        if self.errors.is_empty() {
+            Ok(function)
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+    fn compiler(&self) -> &Compiler<'src> {
+        self.compilers.last().expect("Internal error, no active compiler")
+    }
+    fn compiler_mut(&mut self) -> &mut Compiler<'src> {
+        self.compilers.last_mut().expect("Internal error, no active compiler")
+    }
+    fn class_mut(&mut self) -> &mut ClassCompiler {
+        self.classes.last_mut().expect("Internal error, no active class")
+    }
+    fn declaration(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        if self.match_token(scanner, TokenType::Class) {
+            self.class_declaration(chunk, scanner, vm);
+        } else if self.match_token(scanner, TokenType::Fun) {
+            self.fun_declaration(chunk, scanner, vm);
+        } else if self.match_token(scanner, TokenType::Var) {
+            self.var_declaration(chunk, scanner, vm);
         } else {
-            Ok(())
+            self.statement(chunk, scanner, vm);
+        }
+        if self.panic_mode {
+            self.synchronize(scanner);
+        }
+    }
+    /// Skips tokens until a likely statement boundary so that one error does
+    /// not cascade into many.
+    fn synchronize(&mut self, scanner: &mut Scanner<'src>) {
+        self.panic_mode = false;
+        while self.current.ttype() != TokenType::EOF {
+            if self.previous.ttype() == TokenType::SemiColon {
+                return;
+            }
+            match self.current.ttype() {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                _ => self.advance(scanner),
+            }
         }
     }
-    fn expression(&mut self, chunk: &mut Chunk, scanner: &mut Scanner) {
-        self.parse_precedence(chunk, scanner, Precedence::Assignment);
+    fn class_declaration(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        self.consume(scanner, TokenType::Identifier, "Expect class name.");
+        let name = self.previous;
+        let name_constant = self.identifier_constant(chunk, vm, &name);
+        self.declare_variable();
+        self.emit_bytes(chunk, Byte::from(OpCode::OpClass), name_constant);
+        self.define_variable(chunk, name_constant);
+        self.classes.push(ClassCompiler { has_superclass: false });
+        if self.match_token(scanner, TokenType::Less) {
+            self.consume(scanner, TokenType::Identifier, "Expect superclass name.");
+            self.variable(chunk, scanner, vm, false);
+            if name.as_str() == self.previous.as_str() {
+                self.error("A class can't inherit from itself.");
+            }
+            // The superclass lives in a local named "super" so methods can
+            // capture it like any other variable.
+            self.begin_scope();
+            self.add_local(Token::create_synthetic("super"));
+            self.define_variable(chunk, Byte::from(0));
+            self.named_variable(chunk, scanner, vm, &name, false);
+            self.emit_byte(chunk, Byte::from(OpCode::OpInherit));
+            self.class_mut().has_superclass = true;
+        }
+        self.named_variable(chunk, scanner, vm, &name, false);
+        self.consume(scanner, TokenType::LeftBrace, "Expect '{' before class body.");
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::EOF) {
+            self.method(chunk, scanner, vm);
+        }
+        self.consume(scanner, TokenType::RightBrace, "Expect '}' after class body.");
+        self.emit_byte(chunk, Byte::from(OpCode::OpPop));
+        if self.classes.pop().is_some_and(|class| class.has_superclass) {
+            self.end_scope(chunk);
+        }
+    }
+    fn method(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        self.consume(scanner, TokenType::Identifier, "Expect method name.");
+        let name = self.previous;
+        let constant = self.identifier_constant(chunk, vm, &name);
+        let kind = if name.as_str() == "init" {
+            FunctionType::Initializer
+        } else {
+            FunctionType::Method
+        };
+        self.function(chunk, scanner, vm, kind);
+        self.emit_bytes(chunk, Byte::from(OpCode::OpMethod), constant);
+    }
+    fn fun_declaration(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        let global = self.parse_variable(chunk, scanner, vm, "Expect function name.");
+        self.mark_initialized();
+        self.function(chunk, scanner, vm, FunctionType::Function);
+        self.define_variable(chunk, global);
+    }
+    fn function(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM, kind: FunctionType) {
+        let name = Self::intern(vm, self.previous.as_str());
+        let mut function_chunk = Chunk::new();
+        self.compilers.push(Compiler::new(kind, Some(name)));
+        self.begin_scope();
+        self.consume(scanner, TokenType::LeftParen, "Expect '(' after function name.");
+        if !self.check(TokenType::RightParen) {
+            loop {
+                self.compiler_mut().arity += 1;
+                if self.compiler().arity > 255 {
+                    self.error_at_current("Can't have more than 255 parameters.");
+                }
+                let constant = self.parse_variable(&mut function_chunk, scanner, vm, "Expect parameter name.");
+                self.define_variable(&mut function_chunk, constant);
+                if !self.match_token(scanner, TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(scanner, TokenType::RightParen, "Expect ')' after parameters.");
+        self.consume(scanner, TokenType::LeftBrace, "Expect '{' before function body.");
+        self.block(&mut function_chunk, scanner, vm);
+        let (function, upvalues) = self.end_compiler(function_chunk, vm);
+        let constant = self.make_constant(chunk, Value::create_object(function));
+        self.emit_bytes(chunk, Byte::from(OpCode::OpClosure), constant);
+        for upvalue in upvalues {
+            self.emit_bytes(chunk, Byte::from(usize::from(upvalue.is_local)), Byte::from(upvalue.index));
+        }
+    }
+    fn var_declaration(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        let global = self.parse_variable(chunk, scanner, vm, "Expect variable name.");
+        if self.match_token(scanner, TokenType::Equal) {
+            self.expression(chunk, scanner, vm);
+        } else {
+            self.emit_byte(chunk, Byte::from(OpCode::OpNil));
+        }
+        self.consume(scanner, TokenType::SemiColon, "Expect ';' after variable declaration.");
+        self.define_variable(chunk, global);
+    }
+    fn parse_variable(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM, msg: &str) -> Byte {
+        self.consume(scanner, TokenType::Identifier, msg);
+        self.declare_variable();
+        if self.compiler().scope_depth > 0 {
+            return Byte::from(0);
+        }
+        let name = self.previous;
+        self.identifier_constant(chunk, vm, &name)
+    }
+    fn declare_variable(&mut self) {
+        let scope_depth = self.compiler().scope_depth;
+        if scope_depth == 0 {
+            return;
+        }
+        let name = self.previous;
+        let mut duplicate = false;
+        for local in self.compiler().locals.iter().rev() {
+            if local.depth.is_some_and(|depth| depth < scope_depth) {
+                break;
+            }
+            if local.name.as_str() == name.as_str() {
+                duplicate = true;
+                break;
+            }
+        }
+        if duplicate {
+            self.error("Already a variable with this name in this scope.");
+        }
+        self.add_local(name);
+    }
+    fn add_local(&mut self, name: Token<'src>) {
+        if self.compiler().locals.len() == MAX_LOCALS {
+            self.error("Too many local variables in function.");
+            return;
+        }
+        self.compiler_mut().locals.push(Local { name, depth: None, is_captured: false });
+    }
+    fn resolve_local(&mut self, compiler_idx: usize, name: &Token) -> Option<usize> {
+        let mut uninitialized = false;
+        let mut result = None;
+        for (idx, local) in self.compilers[compiler_idx].locals.iter().enumerate().rev() {
+            if local.name.as_str() == name.as_str() {
+                uninitialized = local.depth.is_none();
+                result = Some(idx);
+                break;
+            }
+        }
+        if uninitialized {
+            self.error("Can't read local variable in its own initializer.");
+        }
+        result
+    }
+    fn resolve_upvalue(&mut self, compiler_idx: usize, name: &Token) -> Option<usize> {
+        if compiler_idx == 0 {
+            return None;
+        }
+        if let Some(local) = self.resolve_local(compiler_idx - 1, name) {
+            self.compilers[compiler_idx - 1].locals[local].is_captured = true;
+            return Some(self.add_upvalue(compiler_idx, local, true));
+        }
+        if let Some(upvalue) = self.resolve_upvalue(compiler_idx - 1, name) {
+            return Some(self.add_upvalue(compiler_idx, upvalue, false));
+        }
+        None
+    }
+    fn add_upvalue(&mut self, compiler_idx: usize, index: usize, is_local: bool) -> usize {
+        let upvalue = Upvalue { index, is_local };
+        let upvalues = &self.compilers[compiler_idx].upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return existing;
+        }
+        if upvalues.len() == MAX_UPVALUES {
+            self.error("Too many closure variables in function.");
+            return 0;
+        }
+        self.compilers[compiler_idx].upvalues.push(upvalue);
+        self.compilers[compiler_idx].upvalues.len() - 1
+    }
+    fn mark_initialized(&mut self) {
+        let depth = self.compiler().scope_depth;
+        if depth == 0 {
+            return;
+        }
+        if let Some(local) = self.compiler_mut().locals.last_mut() {
+            local.depth = Some(depth);
+        }
+    }
+    fn identifier_constant(&mut self, chunk: &mut Chunk, vm: &mut VM, name: &Token) -> Byte {
+        let obj = Self::intern(vm, name.as_str());
+        self.make_constant(chunk, Value::create_object(obj))
+    }
+    fn make_constant(&mut self, chunk: &mut Chunk, value: Value) -> Byte {
+        let idx = chunk.add_constant(value);
+        if idx > 255 {
+            self.error("Too many constants in one chunk.");
+            return Byte::from(0);
+        }
+        Byte::from(idx)
+    }
+    fn define_variable(&mut self, chunk: &mut Chunk, global: Byte) {
+        if self.compiler().scope_depth > 0 {
+            self.mark_initialized();
+            return;
+        }
+        self.emit_bytes(chunk, Byte::from(OpCode::OpDefineGlobal), global);
+    }
+    fn statement(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        if self.match_token(scanner, TokenType::Print) {
+            self.print_statement(chunk, scanner, vm);
+        } else if self.match_token(scanner, TokenType::Return) {
+            self.return_statement(chunk, scanner, vm);
+        } else if self.match_token(scanner, TokenType::If) {
+            self.if_statement(chunk, scanner, vm);
+        } else if self.match_token(scanner, TokenType::While) {
+            self.while_statement(chunk, scanner, vm);
+        } else if self.match_token(scanner, TokenType::For) {
+            self.for_statement(chunk, scanner, vm);
+        } else if self.match_token(scanner, TokenType::LeftBrace) {
+            self.begin_scope();
+            self.block(chunk, scanner, vm);
+            self.end_scope(chunk);
+        } else {
+            self.expression_statement(chunk, scanner, vm);
+        }
+    }
+    fn block(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::EOF) {
+            self.declaration(chunk, scanner, vm);
+        }
+        self.consume(scanner, TokenType::RightBrace, "Expect '}' after block.");
+    }
+    fn begin_scope(&mut self) {
+        self.compiler_mut().scope_depth += 1;
+    }
+    fn end_scope(&mut self, chunk: &mut Chunk) {
+        self.compiler_mut().scope_depth -= 1;
+        let scope_depth = self.compiler().scope_depth;
+        let mut count = 0;
+        while let Some(local) = self.compiler_mut().locals.pop() {
+            if local.depth.is_none_or(|depth| depth <= scope_depth) {
+                self.compiler_mut().locals.push(local);
+                break;
+            }
+            if local.is_captured {
+                self.emit_pops(chunk, count);
+                count = 0;
+                self.emit_byte(chunk, Byte::from(OpCode::OpCloseUpvalue));
+            } else {
+                count += 1;
+            }
+        }
+        self.emit_pops(chunk, count);
+    }
+    fn emit_pops(&self, chunk: &mut Chunk, count: usize) {
+        let mut remaining = count;
+        while remaining > 1 {
+            let n = remaining.min(255);
+            self.emit_bytes(chunk, Byte::from(OpCode::OpPopN), Byte::from(n));
+            remaining -= n;
+        }
+        if remaining == 1 {
+            self.emit_byte(chunk, Byte::from(OpCode::OpPop));
+        }
+    }
+    fn return_statement(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        if self.compiler().kind == FunctionType::Script {
+            self.error("Can't return from top-level code.");
+        }
+        if self.match_token(scanner, TokenType::SemiColon) {
+            self.emit_return(chunk);
+        } else {
+            if self.compiler().kind == FunctionType::Initializer {
+                self.error("Can't return a value from an initializer.");
+            }
+            self.expression(chunk, scanner, vm);
+            self.consume(scanner, TokenType::SemiColon, "Expect ';' after return value.");
+            self.emit_byte(chunk, Byte::from(OpCode::OpReturn));
+        }
+    }
+    fn if_statement(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        self.consume(scanner, TokenType::LeftParen, "Expect '(' after 'if'.");
+        self.expression(chunk, scanner, vm);
+        self.consume(scanner, TokenType::RightParen, "Expect ')' after condition.");
+        let then_jump = self.emit_jump(chunk, OpCode::OpJumpIfFalse);
+        self.emit_byte(chunk, Byte::from(OpCode::OpPop));
+        self.statement(chunk, scanner, vm);
+        let else_jump = self.emit_jump(chunk, OpCode::OpJump);
+        self.patch_jump(chunk, then_jump);
+        self.emit_byte(chunk, Byte::from(OpCode::OpPop));
+        if self.match_token(scanner, TokenType::Else) {
+            self.statement(chunk, scanner, vm);
+        }
+        self.patch_jump(chunk, else_jump);
+    }
+    fn while_statement(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        let loop_start = chunk.code_size();
+        self.consume(scanner, TokenType::LeftParen, "Expect '(' after 'while'.");
+        self.expression(chunk, scanner, vm);
+        self.consume(scanner, TokenType::RightParen, "Expect ')' after condition.");
+        let exit_jump = self.emit_jump(chunk, OpCode::OpJumpIfFalse);
+        self.emit_byte(chunk, Byte::from(OpCode::OpPop));
+        self.statement(chunk, scanner, vm);
+        self.emit_loop(chunk, loop_start);
+        self.patch_jump(chunk, exit_jump);
+        self.emit_byte(chunk, Byte::from(OpCode::OpPop));
+    }
+    fn for_statement(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        self.begin_scope();
+        self.consume(scanner, TokenType::LeftParen, "Expect '(' after 'for'.");
+        if self.match_token(scanner, TokenType::SemiColon) {
+            // No initializer.
+        } else if self.match_token(scanner, TokenType::Var) {
+            self.var_declaration(chunk, scanner, vm);
+        } else {
+            self.expression_statement(chunk, scanner, vm);
+        }
+        let mut loop_start = chunk.code_size();
+        let mut exit_jump = None;
+        if !self.match_token(scanner, TokenType::SemiColon) {
+            self.expression(chunk, scanner, vm);
+            self.consume(scanner, TokenType::SemiColon, "Expect ';' after loop condition.");
+            exit_jump = Some(self.emit_jump(chunk, OpCode::OpJumpIfFalse));
+            self.emit_byte(chunk, Byte::from(OpCode::OpPop));
+        }
+        if !self.match_token(scanner, TokenType::RightParen) {
+            let body_jump = self.emit_jump(chunk, OpCode::OpJump);
+            let increment_start = chunk.code_size();
+            self.expression(chunk, scanner, vm);
+            self.emit_byte(chunk, Byte::from(OpCode::OpPop));
+            self.consume(scanner, TokenType::RightParen, "Expect ')' after for clauses.");
+            self.emit_loop(chunk, loop_start);
+            loop_start = increment_start;
+            self.patch_jump(chunk, body_jump);
+        }
+        self.statement(chunk, scanner, vm);
+        self.emit_loop(chunk, loop_start);
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(chunk, exit_jump);
+            self.emit_byte(chunk, Byte::from(OpCode::OpPop));
+        }
+        self.end_scope(chunk);
+    }
+    fn print_statement(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        self.expression(chunk, scanner, vm);
+        self.consume(scanner, TokenType::SemiColon, "Expect ';' after value.");
+        self.emit_byte(chunk, Byte::from(OpCode::OpPrint));
+    }
+    fn expression_statement(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        self.expression(chunk, scanner, vm);
+        self.consume(scanner, TokenType::SemiColon, "Expect ';' after expression.");
+        self.emit_byte(chunk, Byte::from(OpCode::OpPop));
+    }
+    fn expression(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        self.parse_precedence(chunk, scanner, vm, Precedence::Assignment);
     }
     fn number(&mut self, chunk: &mut Chunk) {
         match self.previous.as_str().parse::<f64>() {
-            Ok(value) => self.emit_constant(chunk, value),
+            Ok(value) => self.emit_constant(chunk, Value::from(value)),
             Err(_) => {
                 self.error("Invalid float constant.");
             },
         }
     }
-    fn grouping(&mut self, chunk: &mut Chunk, scanner: &mut Scanner) {
-        self.expression(chunk, scanner);
+    fn string(&mut self, chunk: &mut Chunk, vm: &mut VM) {
+        let lexeme = self.previous.as_str();
+        let obj = Self::intern(vm, &lexeme[1..lexeme.len() - 1]);
+        self.emit_constant(chunk, Value::create_object(obj));
+    }
+    fn variable(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM, can_assign: bool) {
+        let name = self.previous;
+        self.named_variable(chunk, scanner, vm, &name, can_assign);
+    }
+    fn named_variable(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM, name: &Token, can_assign: bool) {
+        let current = self.compilers.len() - 1;
+        let (get_op, set_op, arg) = if let Some(slot) = self.resolve_local(current, name) {
+            (OpCode::OpGetLocal, OpCode::OpSetLocal, Byte::from(slot))
+        } else if let Some(upvalue) = self.resolve_upvalue(current, name) {
+            (OpCode::OpGetUpvalue, OpCode::OpSetUpvalue, Byte::from(upvalue))
+        } else {
+            let arg = self.identifier_constant(chunk, vm, name);
+            (OpCode::OpGetGlobal, OpCode::OpSetGlobal, arg)
+        };
+        if can_assign && self.match_token(scanner, TokenType::Equal) {
+            self.expression(chunk, scanner, vm);
+            self.emit_bytes(chunk, Byte::from(set_op), arg);
+        } else {
+            self.emit_bytes(chunk, Byte::from(get_op), arg);
+        }
+    }
+    fn this(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        if self.classes.is_empty() {
+            self.error("Can't use 'this' outside of a class.");
+            return;
+        }
+        self.variable(chunk, scanner, vm, false);
+    }
+    fn super_(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        match self.classes.last() {
+            None => self.error("Can't use 'super' outside of a class."),
+            Some(class) if !class.has_superclass => self.error("Can't use 'super' in a class with no superclass."),
+            _ => {},
+        }
+        self.consume(scanner, TokenType::Dot, "Expect '.' after 'super'.");
+        self.consume(scanner, TokenType::Identifier, "Expect superclass method name.");
+        let name = self.previous;
+        let name_constant = self.identifier_constant(chunk, vm, &name);
+        self.named_variable(chunk, scanner, vm, &Token::create_synthetic("this"), false);
+        if self.match_token(scanner, TokenType::LeftParen) {
+            let arg_count = self.argument_list(chunk, scanner, vm);
+            self.named_variable(chunk, scanner, vm, &Token::create_synthetic("super"), false);
+            self.emit_bytes(chunk, Byte::from(OpCode::OpSuperInvoke), name_constant);
+            self.emit_byte(chunk, Byte::from(arg_count));
+        } else {
+            self.named_variable(chunk, scanner, vm, &Token::create_synthetic("super"), false);
+            self.emit_bytes(chunk, Byte::from(OpCode::OpGetSuper), name_constant);
+        }
+    }
+    fn grouping(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        self.expression(chunk, scanner, vm);
         self.consume(scanner, TokenType::RightParen, "Expect ')' after expression.");
     }
-    fn unary(&mut self, chunk: &mut Chunk, scanner: &mut Scanner) {
+    fn unary(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
         let op_type = self.previous.ttype();
-        self.parse_precedence(chunk, scanner, Precedence::Unary);
+        self.parse_precedence(chunk, scanner, vm, Precedence::Unary);
         match op_type {
             TokenType::Minus => self.emit_byte(chunk, Byte::from(OpCode::OpNegate)),
             TokenType::Bang => self.emit_byte(chunk, Byte::from(OpCode::OpNot)),
             _ => {}
         }
     }
-    fn binary(&mut self, scanner: &mut Scanner, chunk: &mut Chunk) {
+    fn binary(&mut self, scanner: &mut Scanner<'src>, chunk: &mut Chunk, vm: &mut VM) {
         let op_type = self.previous.ttype();
         let prec = self.get_rule_precedence(op_type);
-        self.parse_precedence(chunk, scanner, prec.higher());
+        self.parse_precedence(chunk, scanner, vm, prec.higher());
         match op_type {
             TokenType::Plus => self.emit_byte(chunk, Byte::from(OpCode::OpAdd)),
             TokenType::Minus => self.emit_byte(chunk, Byte::from(OpCode::OpSubtract)),
@@ -89,6 +591,56 @@ impl Parser {
             _ => {}
         }
     }
+    fn call(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        let arg_count = self.argument_list(chunk, scanner, vm);
+        self.emit_bytes(chunk, Byte::from(OpCode::OpCall), Byte::from(arg_count));
+    }
+    fn dot(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM, can_assign: bool) {
+        self.consume(scanner, TokenType::Identifier, "Expect property name after '.'.");
+        let name = self.previous;
+        let name_constant = self.identifier_constant(chunk, vm, &name);
+        if can_assign && self.match_token(scanner, TokenType::Equal) {
+            self.expression(chunk, scanner, vm);
+            self.emit_bytes(chunk, Byte::from(OpCode::OpSetProperty), name_constant);
+        } else if self.match_token(scanner, TokenType::LeftParen) {
+            let arg_count = self.argument_list(chunk, scanner, vm);
+            self.emit_bytes(chunk, Byte::from(OpCode::OpInvoke), name_constant);
+            self.emit_byte(chunk, Byte::from(arg_count));
+        } else {
+            self.emit_bytes(chunk, Byte::from(OpCode::OpGetProperty), name_constant);
+        }
+    }
+    fn argument_list(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) -> usize {
+        let mut arg_count = 0;
+        if !self.check(TokenType::RightParen) {
+            loop {
+                self.expression(chunk, scanner, vm);
+                if arg_count == 255 {
+                    self.error("Can't have more than 255 arguments.");
+                }
+                arg_count += 1;
+                if !self.match_token(scanner, TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(scanner, TokenType::RightParen, "Expect ')' after arguments.");
+        arg_count
+    }
+    fn and(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        let end_jump = self.emit_jump(chunk, OpCode::OpJumpIfFalse);
+        self.emit_byte(chunk, Byte::from(OpCode::OpPop));
+        self.parse_precedence(chunk, scanner, vm, Precedence::And);
+        self.patch_jump(chunk, end_jump);
+    }
+    fn or(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM) {
+        let else_jump = self.emit_jump(chunk, OpCode::OpJumpIfFalse);
+        let end_jump = self.emit_jump(chunk, OpCode::OpJump);
+        self.patch_jump(chunk, else_jump);
+        self.emit_byte(chunk, Byte::from(OpCode::OpPop));
+        self.parse_precedence(chunk, scanner, vm, Precedence::Or);
+        self.patch_jump(chunk, end_jump);
+    }
     fn get_rule_precedence(&self, ttype: TokenType) -> Precedence {
         match ttype {
             TokenType::Minus => Precedence::Term,
@@ -101,47 +653,64 @@ impl Parser {
             TokenType::GreateEqual => Precedence::Comparison,
             TokenType::Less => Precedence::Comparison,
             TokenType::LessEqual => Precedence::Comparison,
+            TokenType::And => Precedence::And,
+            TokenType::Or => Precedence::Or,
+            TokenType::LeftParen => Precedence::Call,
+            TokenType::Dot => Precedence::Call,
             _ => Precedence::None,
         }
     }
-    fn parse_precedence(&mut self, chunk: &mut Chunk, scanner: &mut Scanner, precedence: Precedence) {
+    fn parse_precedence(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM, precedence: Precedence) {
         self.advance(scanner);
-        if !self.call_rule_prefix(chunk, scanner, self.previous.ttype()) {
+        let can_assign = precedence <= Precedence::Assignment;
+        if !self.call_rule_prefix(chunk, scanner, vm, self.previous.ttype(), can_assign) {
             self.error("Expect expression.");
+            return;
         }
         while precedence <= self.get_rule_precedence(self.current.ttype()) {
             self.advance(scanner);
-            if !self.call_rule_infix(chunk, scanner, self.previous.ttype()) {
+            if !self.call_rule_infix(chunk, scanner, vm, self.previous.ttype(), can_assign) {
                 self.error("Strange: Missing infix rule!");
                 break
             }
         }
+        if can_assign && self.match_token(scanner, TokenType::Equal) {
+            self.error("Invalid assignment target.");
+        }
     }
-    fn call_rule_infix(&mut self, chunk: &mut Chunk, scanner: &mut Scanner, ttype: TokenType) -> bool {
+    fn call_rule_infix(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM, ttype: TokenType, can_assign: bool) -> bool {
         match ttype {
-            TokenType::Minus => self.binary(scanner, chunk),
-            TokenType::Plus => self.binary(scanner, chunk),
-            TokenType::Slash => self.binary(scanner, chunk),
-            TokenType::Star => self.binary(scanner, chunk),
-            TokenType::BangEqual => self.binary(scanner, chunk),
-            TokenType::EqualEqual => self.binary(scanner, chunk),
-            TokenType::Greater => self.binary(scanner, chunk),
-            TokenType::GreateEqual => self.binary(scanner, chunk),
-            TokenType::Less => self.binary(scanner, chunk),
-            TokenType::LessEqual => self.binary(scanner, chunk),
+            TokenType::Minus => self.binary(scanner, chunk, vm),
+            TokenType::Plus => self.binary(scanner, chunk, vm),
+            TokenType::Slash => self.binary(scanner, chunk, vm),
+            TokenType::Star => self.binary(scanner, chunk, vm),
+            TokenType::BangEqual => self.binary(scanner, chunk, vm),
+            TokenType::EqualEqual => self.binary(scanner, chunk, vm),
+            TokenType::Greater => self.binary(scanner, chunk, vm),
+            TokenType::GreateEqual => self.binary(scanner, chunk, vm),
+            TokenType::Less => self.binary(scanner, chunk, vm),
+            TokenType::LessEqual => self.binary(scanner, chunk, vm),
+            TokenType::And => self.and(chunk, scanner, vm),
+            TokenType::Or => self.or(chunk, scanner, vm),
+            TokenType::LeftParen => self.call(chunk, scanner, vm),
+            TokenType::Dot => self.dot(chunk, scanner, vm, can_assign),
             _ => return false,
         }
         true
     }
-    fn call_rule_prefix(&mut self, chunk: &mut Chunk, scanner: &mut Scanner, ttype: TokenType) -> bool {
+    fn call_rule_prefix(&mut self, chunk: &mut Chunk, scanner: &mut Scanner<'src>, vm: &mut VM, ttype: TokenType, can_assign: bool) -> bool {
         match ttype {
-            TokenType::LeftParen => self.grouping(chunk, scanner),
-            TokenType::Minus => self.unary(chunk, scanner),
+            TokenType::LeftParen => self.grouping(chunk, scanner, vm),
+            TokenType::Minus => self.unary(chunk, scanner, vm),
             TokenType::Number => self.number(chunk),
+            TokenType::String => self.string(chunk, vm),
+            TokenType::Identifier => self.variable(chunk, scanner, vm, can_assign),
+            TokenType::This => self.this(chunk, scanner, vm),
+            TokenType::Super => self.super_(chunk, scanner, vm),
             TokenType::Nil => self.literal(chunk),
             TokenType::True => self.literal(chunk),
             TokenType::False => self.literal(chunk),
-            TokenType::Bang => self.unary(chunk, scanner),
+            TokenType::Bang => self.unary(chunk, scanner, vm),
             _ => return false,
         }
         true
@@ -154,11 +723,25 @@ impl Parser {
             _ => panic!("Strange literal!"),
         }
     }
-    fn emit_constant(&self, chunk: &mut Chunk, value: f64) {
-        chunk.write_const(Value::from(value), self.previous.line());
+    fn emit_constant(&self, chunk: &mut Chunk, value: Value) {
+        chunk.write_const(value, self.previous.line());
     }
-    fn end_compiler(&self, chunk: &mut Chunk) {
-        self.emit_return(chunk);
+    fn end_compiler(&mut self, mut chunk: Chunk, vm: &mut VM) -> (ObjRef, Vec<Upvalue>) {
+        self.emit_return(&mut chunk);
+        let compiler = self.compilers.pop().expect("Internal error, no active compiler");
+        let function = ObjFunction::new(compiler.arity, compiler.upvalues.len(), chunk, compiler.name);
+        #[cfg(feature = "dumpChunk")]
+        if self.errors.is_empty() {
+            let _ = disassembler::dump_chunk(&mut *vm.diagnostics().borrow_mut(), function.chunk(), function.name());
+        }
+        let function = vm.alloc_function(function);
+        vm.add_compiler_root(function);
+        (function, compiler.upvalues)
+    }
+    fn intern(vm: &mut VM, chars: &str) -> ObjRef {
+        let obj = vm.intern(chars);
+        vm.add_compiler_root(obj);
+        obj
     }
     fn emit_byte(&self, chunk: &mut Chunk, byte: Byte) {
         chunk.write_code(byte, self.previous.line());
@@ -167,18 +750,55 @@ impl Parser {
         chunk.write_code(byte1, self.previous.line());
         chunk.write_code(byte2, self.previous.line());
     }
+    fn emit_jump(&self, chunk: &mut Chunk, instruction: OpCode) -> usize {
+        self.emit_byte(chunk, Byte::from(instruction));
+        self.emit_bytes(chunk, Byte::from(0xff), Byte::from(0xff));
+        chunk.code_size() - 2
+    }
+    fn patch_jump(&mut self, chunk: &mut Chunk, offset: usize) {
+        let jump = chunk.code_size() - offset - 2;
+        if jump > u16::MAX as usize {
+            self.error("Too much code to jump over.");
+            return;
+        }
+        chunk.patch_code(offset, Byte::from((jump >> 8) & 0xff));
+        chunk.patch_code(offset + 1, Byte::from(jump & 0xff));
+    }
+    fn emit_loop(&mut self, chunk: &mut Chunk, loop_start: usize) {
+        self.emit_byte(chunk, Byte::from(OpCode::OpLoop));
+        let offset = chunk.code_size() - loop_start + 2;
+        if offset > u16::MAX as usize {
+            self.error("Loop body too large.");
+        }
+        self.emit_bytes(chunk, Byte::from((offset >> 8) & 0xff), Byte::from(offset & 0xff));
+    }
     fn emit_return(&self, chunk: &mut Chunk) {
+        if self.compiler().kind == FunctionType::Initializer {
+            self.emit_bytes(chunk, Byte::from(OpCode::OpGetLocal), Byte::from(0));
+        } else {
+            self.emit_byte(chunk, Byte::from(OpCode::OpNil));
+        }
         self.emit_byte(chunk, Byte::from(OpCode::OpReturn));
     }
-    fn consume(&mut self, scanner: &mut Scanner, ttype: TokenType, msg: &str) {
+    fn consume(&mut self, scanner: &mut Scanner<'src>, ttype: TokenType, msg: &str) {
         if self.current.ttype() == ttype {
             self.advance(scanner);
         } else {
             self.error_at_current(msg);
         }
     }
-    fn advance(&mut self, scanner: &mut Scanner) {
-        self.previous = self.current.clone();
+    fn check(&self, ttype: TokenType) -> bool {
+        self.current.ttype() == ttype
+    }
+    fn match_token(&mut self, scanner: &mut Scanner<'src>, ttype: TokenType) -> bool {
+        if !self.check(ttype) {
+            return false;
+        }
+        self.advance(scanner);
+        true
+    }
+    fn advance(&mut self, scanner: &mut Scanner<'src>) {
+        self.previous = self.current;
         loop {
             self.current = scanner.scan_token();
             if self.current.ttype() != TokenType::Error {
@@ -189,11 +809,11 @@ impl Parser {
         }
     }
     fn error_at_current(&mut self, msg: &str) {
-        let error_token = self.current.clone();
+        let error_token = self.current;
         self.error_at(&error_token, msg);
     }
     fn error(&mut self, msg: &str) {
-        let error_token = self.previous.clone();
+        let error_token = self.previous;
         self.error_at(&error_token, msg);
     }
     fn error_at(&mut self, token: &Token, msg: &str) {
@@ -201,15 +821,15 @@ impl Parser {
             return;
         }
         self.panic_mode = true;
-        eprint!("[line {}:{}] Error", token.line(), token.column() - token.as_str().len());
-        if token.ttype() == TokenType::EOF {
-            eprint!(" at end");
-        } else if token.ttype() == TokenType::Error {
-        } else {
-            eprint!(" at '{}'", token.as_str());
-        }
-        eprintln!(": {}", msg);
-        self.set_error(true);
+        let location = match token.ttype() {
+            TokenType::EOF => String::from(" at end"),
+            TokenType::Error => String::new(),
+            _ => format!(" at '{}'", token.as_str()),
+        };
+        let caret_length = self.source.get(token.span()).map_or(0, |lexeme| lexeme.chars().count());
+        let source_line = self.source.split('\n').nth(token.line().saturating_sub(1)).unwrap_or("");
+        let error = CompileError::new(msg, &self.source_name, token.line(), token.column(), caret_length, &location, source_line.trim_end_matches('\r'));
+        self.errors.push(error);
     }
 }
 
@@ -231,13 +851,13 @@ enum Precedence {
 impl Precedence {
     fn higher(&self) -> Precedence {
         let value = (*self as u8) + 1;
-        Precedence::try_from(value).expect("Internal error, invalid precedence")
+        Precedence::from(value)
     }
 }
 
-impl Into<Precedence> for u8 {
-    fn into(self) -> Precedence {
-        match self {
+impl From<u8> for Precedence {
+    fn from(value: u8) -> Precedence {
+        match value {
             0 => Precedence::None,
             1 => Precedence::Assignment,
             2 => Precedence::Or,
@@ -251,4 +871,21 @@ impl Into<Precedence> for u8 {
             _ => Precedence::Primary,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::virtual_machine::VM;
+
+    #[test]
+    fn independent_compile_errors_are_all_reported() {
+        let mut vm = VM::new();
+        let source = "var = 1;\nprint 2;\nprint (3;\nvar x = 4 4;\nprint this;";
+        let Err(errors) = vm.compile("script", source) else {
+            panic!("expected compile errors");
+        };
+        let lines: Vec<usize> = errors.iter().map(|e| e.line()).collect();
+        assert_eq!(lines, vec![1, 3, 4, 5]);
+        assert_eq!(errors[3].message(), "Can't use 'this' outside of a class.");
+    }
+}
@@ -1,73 +1,143 @@
-#[cfg(any(feature = "dumpChunk", feature = "traceExecution"))]
+use std::io::{self, Write};
+
 use crate::chunk;
+use crate::objects::ObjFunction;
 
-#[cfg(any(feature = "dumpChunk"))]
-pub fn dump_chunk(chunk: &chunk::Chunk, name: &str) {
-    println!("== {} ==", name);
+pub fn dump_chunk(out: &mut dyn Write, chunk: &chunk::Chunk, name: &str) -> io::Result<()> {
+    writeln!(out, "== {} ==", name)?;
     let mut offset: usize = 0;
     while offset < chunk.code_size() {
-        offset = dump_instruction(chunk, offset);
+        offset = dump_instruction(out, chunk, offset)?;
+    }
+    Ok(())
+}
+
+/// Dumps `function` and every function nested in it, innermost first, which
+/// is the order the compiler finishes them in.
+pub fn dump_function(out: &mut dyn Write, function: &ObjFunction) -> io::Result<()> {
+    for constant in function.chunk().constants() {
+        if let Ok(obj) = constant.try_as_object() {
+            if let Some(nested) = obj.as_function() {
+                dump_function(out, nested)?;
+            }
+        }
     }
+    dump_chunk(out, function.chunk(), function.name())
 }
 
-#[cfg(any(feature = "dumpChunk", feature = "traceExecution"))]
-pub fn dump_instruction(chunk: &chunk::Chunk, offset: usize) -> usize {
+pub fn dump_instruction(out: &mut dyn Write, chunk: &chunk::Chunk, offset: usize) -> io::Result<usize> {
     use crate::chunk;
 
-    print!("{:04} ", offset);
+    write!(out, "{:04} ", offset)?;
     if offset > 0 && chunk.read_line(offset) == chunk.read_line(offset - 1) {
-        print!("   | ");
+        write!(out, "   | ")?;
     } else {
-        print!("{:4} ", chunk.read_line(offset))
+        write!(out, "{:4} ", chunk.read_line(offset))?;
     }
     let instr_code = chunk.read_code(offset);
     if let Ok(instr) = chunk::OpCode::try_from(instr_code) {
         match instr {
-            chunk::OpCode::OpReturn => dump_simple_instr("OP_RETURN", offset),
-            chunk::OpCode::OpConstantLong => dump_long_constant_instr("OP_CONSTANT_LONG", chunk, offset),
-            chunk::OpCode::OpConstant => dump_constant_instr("OP_CONSTANT", chunk, offset),
-            chunk::OpCode::OpNegate => dump_simple_instr("OP_NEGATE", offset),
-            chunk::OpCode::OpAdd => dump_simple_instr("OP_ADD", offset),
-            chunk::OpCode::OpSubtract => dump_simple_instr("OP_SUBTRACT", offset),
-            chunk::OpCode::OpMultiply => dump_simple_instr("OP_MULTIPLY", offset),
-            chunk::OpCode::OpDivide => dump_simple_instr("OP_DIVIDE", offset),
-            chunk::OpCode::OpNil => dump_simple_instr("OP_NIL", offset),
-            chunk::OpCode::OpTrue => dump_simple_instr("OP_TRUE", offset),
-            chunk::OpCode::OpFalse => dump_simple_instr("OP_FALSE", offset),
-            chunk::OpCode::OpNot => dump_simple_instr("OP_NOT", offset),
-            chunk::OpCode::OpEqual => dump_simple_instr("OP_EQUAL", offset),
-            chunk::OpCode::OpGreater => dump_simple_instr("OP_GREATER", offset),
-            chunk::OpCode::OpLess => dump_simple_instr("OP_LESS", offset),
+            chunk::OpCode::OpReturn => dump_simple_instr(out, "OP_RETURN", offset),
+            chunk::OpCode::OpConstantLong => dump_long_constant_instr(out, "OP_CONSTANT_LONG", chunk, offset),
+            chunk::OpCode::OpConstant => dump_constant_instr(out, "OP_CONSTANT", chunk, offset),
+            chunk::OpCode::OpNegate => dump_simple_instr(out, "OP_NEGATE", offset),
+            chunk::OpCode::OpAdd => dump_simple_instr(out, "OP_ADD", offset),
+            chunk::OpCode::OpSubtract => dump_simple_instr(out, "OP_SUBTRACT", offset),
+            chunk::OpCode::OpMultiply => dump_simple_instr(out, "OP_MULTIPLY", offset),
+            chunk::OpCode::OpDivide => dump_simple_instr(out, "OP_DIVIDE", offset),
+            chunk::OpCode::OpNil => dump_simple_instr(out, "OP_NIL", offset),
+            chunk::OpCode::OpTrue => dump_simple_instr(out, "OP_TRUE", offset),
+            chunk::OpCode::OpFalse => dump_simple_instr(out, "OP_FALSE", offset),
+            chunk::OpCode::OpNot => dump_simple_instr(out, "OP_NOT", offset),
+            chunk::OpCode::OpEqual => dump_simple_instr(out, "OP_EQUAL", offset),
+            chunk::OpCode::OpGreater => dump_simple_instr(out, "OP_GREATER", offset),
+            chunk::OpCode::OpLess => dump_simple_instr(out, "OP_LESS", offset),
+            chunk::OpCode::OpPrint => dump_simple_instr(out, "OP_PRINT", offset),
+            chunk::OpCode::OpPop => dump_simple_instr(out, "OP_POP", offset),
+            chunk::OpCode::OpPopN => dump_byte_instr(out, "OP_POP_N", chunk, offset),
+            chunk::OpCode::OpGetLocal => dump_byte_instr(out, "OP_GET_LOCAL", chunk, offset),
+            chunk::OpCode::OpSetLocal => dump_byte_instr(out, "OP_SET_LOCAL", chunk, offset),
+            chunk::OpCode::OpDefineGlobal => dump_constant_instr(out, "OP_DEFINE_GLOBAL", chunk, offset),
+            chunk::OpCode::OpGetGlobal => dump_constant_instr(out, "OP_GET_GLOBAL", chunk, offset),
+            chunk::OpCode::OpSetGlobal => dump_constant_instr(out, "OP_SET_GLOBAL", chunk, offset),
+            chunk::OpCode::OpJump => dump_jump_instr(out, "OP_JUMP", 1, chunk, offset),
+            chunk::OpCode::OpJumpIfFalse => dump_jump_instr(out, "OP_JUMP_IF_FALSE", 1, chunk, offset),
+            chunk::OpCode::OpLoop => dump_jump_instr(out, "OP_LOOP", -1, chunk, offset),
+            chunk::OpCode::OpCall => dump_byte_instr(out, "OP_CALL", chunk, offset),
+            chunk::OpCode::OpClosure => dump_closure_instr(out, "OP_CLOSURE", chunk, offset),
+            chunk::OpCode::OpGetUpvalue => dump_byte_instr(out, "OP_GET_UPVALUE", chunk, offset),
+            chunk::OpCode::OpSetUpvalue => dump_byte_instr(out, "OP_SET_UPVALUE", chunk, offset),
+            chunk::OpCode::OpCloseUpvalue => dump_simple_instr(out, "OP_CLOSE_UPVALUE", offset),
+            chunk::OpCode::OpClass => dump_constant_instr(out, "OP_CLASS", chunk, offset),
+            chunk::OpCode::OpGetProperty => dump_constant_instr(out, "OP_GET_PROPERTY", chunk, offset),
+            chunk::OpCode::OpSetProperty => dump_constant_instr(out, "OP_SET_PROPERTY", chunk, offset),
+            chunk::OpCode::OpMethod => dump_constant_instr(out, "OP_METHOD", chunk, offset),
+            chunk::OpCode::OpInvoke => dump_invoke_instr(out, "OP_INVOKE", chunk, offset),
+            chunk::OpCode::OpInherit => dump_simple_instr(out, "OP_INHERIT", offset),
+            chunk::OpCode::OpGetSuper => dump_constant_instr(out, "OP_GET_SUPER", chunk, offset),
+            chunk::OpCode::OpSuperInvoke => dump_invoke_instr(out, "OP_SUPER_INVOKE", chunk, offset),
         }
     } else {
-        println!("Unknown opcode {}", instr_code);
-        offset + 1
+        writeln!(out, "Unknown opcode {}", instr_code)?;
+        Ok(offset + 1)
     }
 }
 
-#[cfg(any(feature = "dumpChunk", feature = "traceExecution"))]
-fn dump_simple_instr(name: &str, offset: usize) -> usize {
-    println!("{:16}", name);
-    offset + 1
+fn dump_simple_instr(out: &mut dyn Write, name: &str, offset: usize) -> io::Result<usize> {
+    writeln!(out, "{:16}", name)?;
+    Ok(offset + 1)
+}
+
+fn dump_byte_instr(out: &mut dyn Write, name: &str, chunk: &chunk::Chunk, offset: usize) -> io::Result<usize> {
+    let operand = chunk.read_code(offset + 1);
+    writeln!(out, "{:16} {:8}", name, operand)?;
+    Ok(offset + 2)
+}
+
+fn dump_jump_instr(out: &mut dyn Write, name: &str, sign: i64, chunk: &chunk::Chunk, offset: usize) -> io::Result<usize> {
+    let jump = usize::from(chunk.read_code(offset + 1)) << 8 | usize::from(chunk.read_code(offset + 2));
+    let target = offset as i64 + 3 + sign * jump as i64;
+    writeln!(out, "{:16} {:8} -> {}", name, offset, target)?;
+    Ok(offset + 3)
+}
+
+fn dump_constant_instr(out: &mut dyn Write, name: &str, chunk: &chunk::Chunk, offset: usize) -> io::Result<usize> {
+    let constant = usize::from(chunk.read_code(offset + 1));
+    writeln!(out, "{:16} {:8} '{}'", name, constant, chunk.read_constant(constant))?;
+    Ok(offset + 2)
 }
 
-#[cfg(any(feature = "dumpChunk", feature = "traceExecution"))]
-fn dump_constant_instr(name: &str, chunk: &chunk::Chunk, offset: usize) -> usize {
+fn dump_invoke_instr(out: &mut dyn Write, name: &str, chunk: &chunk::Chunk, offset: usize) -> io::Result<usize> {
     let constant = usize::from(chunk.read_code(offset + 1));
-    print!("{:16} {:8} '", name, constant);
-    chunk.read_constant(constant).print();
-    println!("'");
-    offset + 2
+    let arg_count = chunk.read_code(offset + 2);
+    writeln!(out, "{:16} ({} args) {:4} '{}'", name, arg_count, constant, chunk.read_constant(constant))?;
+    Ok(offset + 3)
 }
 
-#[cfg(any(feature = "dumpChunk", feature = "traceExecution"))]
-fn dump_long_constant_instr(name: &str, chunk: &chunk::Chunk, offset: usize) -> usize {
+fn dump_long_constant_instr(out: &mut dyn Write, name: &str, chunk: &chunk::Chunk, offset: usize) -> io::Result<usize> {
     let c1 = chunk.read_code(offset + 1);
     let c2 = chunk.read_code(offset + 2);
     let c3 = chunk.read_code(offset + 3);
     let idx = usize::from(c1) << 16 | usize::from(c2) << 8 | usize::from(c3);
-    print!("{:16} {:8} '", name, idx);
-    chunk.read_constant(usize::from(idx)).print();
-    println!("'");
-    offset + 4
+    writeln!(out, "{:16} {:8} '{}'", name, idx, chunk.read_constant(idx))?;
+    Ok(offset + 4)
+}
+
+fn dump_closure_instr(out: &mut dyn Write, name: &str, chunk: &chunk::Chunk, offset: usize) -> io::Result<usize> {
+    let constant = usize::from(chunk.read_code(offset + 1));
+    let function = chunk.read_constant(constant);
+    writeln!(out, "{:16} {:8} {}", name, constant, function)?;
+    let upvalue_count = function
+        .try_as_object()
+        .ok()
+        .and_then(|obj| obj.as_function().map(|f| f.upvalue_count()))
+        .unwrap_or(0);
+    let mut offset = offset + 2;
+    for _ in 0..upvalue_count {
+        let is_local = usize::from(chunk.read_code(offset)) == 1;
+        let index = chunk.read_code(offset + 1);
+        writeln!(out, "{:04}    |                     {} {}", offset, if is_local { "local" } else { "upvalue" }, index)?;
+        offset += 2;
+    }
+    Ok(offset)
 }
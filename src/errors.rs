@@ -0,0 +1,211 @@
+use std::error::Error;
+use std::fmt;
+
+/// Error returned by `VM::interpret`.
+#[derive(Debug, Clone)]
+pub enum InterpretError {
+    CompileError(Vec<CompileError>),
+    RuntimeError(RuntimeError),
+}
+
+impl fmt::Display for InterpretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpretError::CompileError(errors) => {
+                for (idx, error) in errors.iter().enumerate() {
+                    if idx > 0 {
+                        writeln!(f)?;
+                    }
+                    error.fmt(f)?;
+                }
+                Ok(())
+            },
+            InterpretError::RuntimeError(error) => error.fmt(f),
+        }
+    }
+}
+
+// `Display` already prints the inner errors in full, so they are not
+// reported again as a source.
+impl Error for InterpretError {}
+
+impl From<Vec<CompileError>> for InterpretError {
+    fn from(errors: Vec<CompileError>) -> InterpretError {
+        InterpretError::CompileError(errors)
+    }
+}
+
+impl From<RuntimeError> for InterpretError {
+    fn from(error: RuntimeError) -> InterpretError {
+        InterpretError::RuntimeError(error)
+    }
+}
+
+/// A syntax or resolution error found by the compiler.
+#[derive(Debug, Clone)]
+pub struct CompileError {
+    message: String,
+    source_name: String,
+    line: usize,
+    column: usize,
+    length: usize,
+    location: String,
+    source_line: String,
+}
+
+impl CompileError {
+    pub fn new(message: &str, source_name: &str, line: usize, column: usize, length: usize, location: &str, source_line: &str) -> CompileError {
+        CompileError {
+            message: message.to_string(),
+            source_name: source_name.to_string(),
+            line,
+            column,
+            length,
+            location: location.to_string(),
+            source_line: source_line.to_string(),
+        }
+    }
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+    pub fn source_name(&self) -> &str {
+        &self.source_name
+    }
+    pub fn line(&self) -> usize {
+        self.line
+    }
+    /// Column of the first offending character, counting characters.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+    /// Where on the line the error was found, such as ` at 'x'` or ` at end`.
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+    /// Renders the error followed by the offending source line with the
+    /// token underlined, using ANSI colors if `color` is set.
+    pub fn render(&self, color: bool) -> String {
+        let (red, blue, reset) = if color {
+            ("\x1b[1;31m", "\x1b[1;34m", "\x1b[0m")
+        } else {
+            ("", "", "")
+        };
+        let mut out = format!("{}[line {}:{}] Error{}{}: {}", red, self.line, self.column, self.location, reset, self.message);
+        if self.line == 0 {
+            return out;
+        }
+        // Keep tabs in the padding so the carets line up with the source.
+        let padding: String = self.source_line.chars()
+            .take(self.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let remaining = self.source_line.chars().count().saturating_sub(padding.chars().count());
+        let carets = "^".repeat(self.length.min(remaining).max(1));
+        let gutter = " ".repeat(self.line.to_string().len());
+        out += &format!("\n{}{} |{}", blue, gutter, reset);
+        out += &format!("\n{}{} |{} {}", blue, self.line, reset, self.source_line);
+        out += &format!("\n{}{} |{} {}{}{}{}", blue, gutter, reset, padding, red, carets, reset);
+        out
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render(false))
+    }
+}
+
+impl Error for CompileError {}
+
+/// One active call at the time of a runtime error. Top-level code has no
+/// function name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    function: Option<String>,
+    line: usize,
+}
+
+impl TraceFrame {
+    pub fn new(function: Option<&str>, line: usize) -> TraceFrame {
+        TraceFrame { function: function.map(|name| name.to_string()), line }
+    }
+    pub fn function(&self) -> Option<&str> {
+        self.function.as_deref()
+    }
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function {
+            Some(name) => write!(f, "[line {}] in {}()", self.line, name),
+            None => write!(f, "[line {}] in script", self.line),
+        }
+    }
+}
+
+/// An error raised while running a script, either by the VM itself or by a
+/// native function. Natives only provide the message; the VM fills in the
+/// location and the call stack before handing the error to the caller.
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    message: String,
+    source_name: String,
+    trace: Vec<TraceFrame>,
+}
+
+impl RuntimeError {
+    pub fn new(message: &str) -> RuntimeError {
+        RuntimeError { message: message.to_string(), source_name: String::new(), trace: vec![] }
+    }
+    pub fn with_trace(mut self, source_name: &str, trace: Vec<TraceFrame>) -> RuntimeError {
+        self.source_name = source_name.to_string();
+        self.trace = trace;
+        self
+    }
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+    pub fn source_name(&self) -> &str {
+        &self.source_name
+    }
+    /// Line of the instruction that failed, or 0 if the error never reached
+    /// the VM.
+    pub fn line(&self) -> usize {
+        self.trace.first().map_or(0, |frame| frame.line)
+    }
+    /// The call stack when the error was raised, innermost call first.
+    pub fn trace(&self) -> &[TraceFrame] {
+        &self.trace
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for frame in &self.trace {
+            write!(f, "\n{}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for RuntimeError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::virtual_machine::VM;
+
+    #[test]
+    fn compile_errors_underline_the_offending_token() {
+        let mut vm = VM::new();
+        let Err(errors) = vm.compile("script", "\tvar x = 1 +;\nprint nope nope;") else {
+            panic!("expected compile errors");
+        };
+        assert_eq!(errors[0].column(), 13);
+        assert_eq!(errors[0].render(false), "[line 1:13] Error at ';': Expect expression.\n  |\n1 | \tvar x = 1 +;\n  | \t           ^");
+        assert_eq!(errors[1].to_string().lines().last(), Some("  |            ^^^^"));
+    }
+}
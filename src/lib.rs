@@ -1,12 +1,50 @@
-use std::{env, fs, io::{self, Write}, process::ExitCode};
+//! A bytecode interpreter for Lox.
+//!
+//! Besides the `rlox` binary, the crate can be embedded as a scripting
+//! engine:
+//!
+//! ```
+//! let mut vm = rlox::VM::new();
+//! vm.set_global("base", 40.0).unwrap();
+//! vm.interpret_named("example.lox", "var answer = base + 2;").unwrap();
+//! assert_eq!(vm.get_global("answer"), Some(rlox::HostValue::Number(42.0)));
+//! ```
+//!
+//! Output of `print` statements goes to stdout unless redirected with
+//! `VM::set_output`.
+use std::{env, fs, io::{self, IsTerminal, Write}, process::ExitCode};
 
 mod chunk;
 mod compiler;
 mod disassembler;
+mod errors;
+mod memory;
+mod natives;
+mod objects;
 mod scanner;
 mod values;
 mod virtual_machine;
 
+pub use objects::NativeFn;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub use scanner::{Scanner, Token, TokenType};
+pub use values::{HostValue, Value};
+pub use errors::{CompileError, InterpretError, RuntimeError, TraceFrame};
+pub use virtual_machine::VM;
+
+fn report(error: &InterpretError) {
+    match error {
+        InterpretError::CompileError(errors) => {
+            let color = io::stderr().is_terminal();
+            for error in errors {
+                eprintln!("{}", error.render(color));
+            }
+        },
+        InterpretError::RuntimeError(error) => eprintln!("{}", error),
+    }
+}
+
 fn repl(vm: &mut virtual_machine::VM) {
     let mut line = String::new();
     let stdin = io::stdin();
@@ -14,33 +52,79 @@ fn repl(vm: &mut virtual_machine::VM) {
         print!("> ");
         io::stdout().flush().expect("Fluch fail");
         line.clear();
-        if let Err(_) = stdin.read_line(&mut line) {
-            println!("");
+        if stdin.read_line(&mut line).is_err() {
+            println!();
             break;
         }
-        if line == "" {
+        if line.is_empty() {
             break;
         }
-        if let Err(e) = vm.interpret(&line) {
-            match e {
-                virtual_machine::InterpretError::CompileError => eprintln!("Compilation error!"),
-                virtual_machine::InterpretError::RuntimeError => eprintln!("Runtime error!"),
-            }
+        if let Err(e) = vm.interpret_named("repl", &line) {
+            report(&e);
         }
     }
 }
 
+fn read_source(path: &str) -> Result<String, ExitCode> {
+    fs::read_to_string(path).map_err(|error| {
+        eprintln!("Could not read file \"{}\": {}", path, error);
+        ExitCode::from(74)
+    })
+}
+
 fn run_file(vm: &mut virtual_machine::VM, path: &str) -> Result<(), ExitCode> {
-    let contents = fs::read_to_string(path);
-    match contents {
-        Err(_) => {
-            Err(ExitCode::from(74))
+    let content = read_source(path)?;
+    match vm.interpret_named(path, &content) {
+        Err(e @ InterpretError::CompileError(_)) => {
+            report(&e);
+            Err(ExitCode::from(65))
         },
-        Ok(content) => {
-            let result = vm.interpret(&content);
-            if let Err(_) = result {
-                return Err(ExitCode::FAILURE);
-            }
+        Err(e @ InterpretError::RuntimeError(_)) => {
+            report(&e);
+            Err(ExitCode::from(70))
+        },
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Prints every token of the file with its position. Scanning errors are
+/// printed as `<ERROR>` tokens carrying the message.
+fn tokenize_file(path: &str) -> Result<(), ExitCode> {
+    let content = read_source(path)?;
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let mut scanner = scanner::Scanner::init(&content);
+    let mut had_error = false;
+    loop {
+        let token = scanner.scan_token();
+        let line = format!("{:>4}:{:<4} {:<14} {}", token.line(), token.column(), token.ttype().to_string(), token.as_str());
+        let _ = writeln!(out, "{}", line.trim_end());
+        match token.ttype() {
+            scanner::TokenType::EOF => break,
+            scanner::TokenType::Error => had_error = true,
+            _ => {},
+        }
+    }
+    let _ = out.flush();
+    if had_error {
+        Err(ExitCode::from(65))
+    } else {
+        Ok(())
+    }
+}
+
+/// Prints the bytecode of the file without running it.
+fn parse_file(vm: &mut virtual_machine::VM, path: &str) -> Result<(), ExitCode> {
+    let content = read_source(path)?;
+    match vm.compile(path, &content) {
+        Err(errors) => {
+            report(&InterpretError::CompileError(errors));
+            Err(ExitCode::from(65))
+        },
+        Ok(function) => {
+            let mut out = io::BufWriter::new(io::stdout().lock());
+            let function = function.as_function().expect("Internal error, compiled script is not a function");
+            let _ = disassembler::dump_function(&mut out, function);
+            let _ = out.flush();
             Ok(())
         },
     }
@@ -50,16 +134,22 @@ pub fn run() -> ExitCode {
     let mut vm = virtual_machine::VM::new();
 
     let args: Vec<String> = env::args().collect();
-    if args.len() == 1 {
-        repl(&mut vm);
-    } else if args.len() == 2 {
-        if let Err(ec) = run_file(&mut vm, &args[1]) {
-            return ec;
-        }
-    } else {
-        eprintln!("Usage: {} <cmd> <filename>", args[0]);
-        eprintln!("          <cmd> is one of tokenize or parse");
-        return ExitCode::FAILURE;
+    let result = match args.as_slice() {
+        [_] => {
+            repl(&mut vm);
+            Ok(())
+        },
+        [_, cmd, path] if cmd == "tokenize" => tokenize_file(path),
+        [_, cmd, path] if cmd == "parse" => parse_file(&mut vm, path),
+        [_, path] if path != "tokenize" && path != "parse" => run_file(&mut vm, path),
+        _ => {
+            eprintln!("Usage: {} [[<cmd>] <filename>]", args[0]);
+            eprintln!("          <cmd> is one of tokenize or parse");
+            Err(ExitCode::FAILURE)
+        },
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(ec) => ec,
     }
-    ExitCode::SUCCESS
 }
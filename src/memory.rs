@@ -0,0 +1,162 @@
+use crate::objects::{Obj, ObjKind, ObjRef, ObjString, UpvalueState};
+use crate::values::Value;
+#[cfg(feature = "logGc")]
+use crate::virtual_machine::Sink;
+
+const GC_INITIAL_THRESHOLD: usize = 1024 * 1024;
+const GC_HEAP_GROW_FACTOR: usize = 2;
+
+/// Owner of every object allocated by the compiler and the VM.
+///
+/// The heap only knows how to trace and free objects; finding the roots is
+/// up to the VM, which marks them before asking the heap to finish the
+/// collection.
+pub struct Heap {
+    objects: Vec<ObjRef>,
+    gray_stack: Vec<ObjRef>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    grow_factor: usize,
+    #[cfg(feature = "logGc")]
+    log: Sink,
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap {
+            objects: vec![],
+            gray_stack: vec![],
+            bytes_allocated: 0,
+            next_gc: GC_INITIAL_THRESHOLD,
+            grow_factor: GC_HEAP_GROW_FACTOR,
+            #[cfg(feature = "logGc")]
+            log: std::rc::Rc::new(std::cell::RefCell::new(std::io::stderr())),
+        }
+    }
+    #[cfg(feature = "logGc")]
+    pub fn set_log(&mut self, log: Sink) {
+        self.log = log;
+    }
+    /// Sets how many bytes may be allocated before the next collection.
+    pub fn set_next_gc(&mut self, bytes: usize) {
+        self.next_gc = bytes;
+    }
+    /// Sets the multiple of the surviving bytes at which the collection
+    /// after next is scheduled.
+    pub fn set_grow_factor(&mut self, factor: usize) {
+        self.grow_factor = factor;
+    }
+    pub fn alloc(&mut self, kind: ObjKind) -> ObjRef {
+        let obj = ObjRef::from_box(Box::new(Obj::new(kind)));
+        let size = obj.borrow().size();
+        self.bytes_allocated += size;
+        self.objects.push(obj);
+        #[cfg(feature = "logGc")]
+        let _ = writeln!(self.log.borrow_mut(), "{:?} allocate {} for {}", obj, size, obj.borrow().type_name());
+        obj
+    }
+    pub fn alloc_string(&mut self, chars: String) -> ObjRef {
+        self.alloc(ObjKind::String(ObjString::new(chars)))
+    }
+    pub fn should_collect(&self) -> bool {
+        cfg!(feature = "gcStress") || self.bytes_allocated > self.next_gc
+    }
+    pub fn mark_value(&mut self, value: Value) {
+        if let Ok(obj) = value.try_as_object() {
+            self.mark_object(obj);
+        }
+    }
+    pub fn mark_object(&mut self, obj: ObjRef) {
+        if obj.is_marked() {
+            return;
+        }
+        #[cfg(feature = "logGc")]
+        let _ = writeln!(self.log.borrow_mut(), "{:?} mark {}", obj, obj);
+        obj.borrow().set_marked(true);
+        self.gray_stack.push(obj);
+    }
+    pub fn trace_references(&mut self) {
+        while let Some(obj) = self.gray_stack.pop() {
+            self.blacken_object(obj);
+        }
+    }
+    fn blacken_object(&mut self, obj: ObjRef) {
+        #[cfg(feature = "logGc")]
+        let _ = writeln!(self.log.borrow_mut(), "{:?} blacken {}", obj, obj);
+        match obj.borrow().kind() {
+            ObjKind::String(_) => {},
+            ObjKind::Function(function) => {
+                if let Some(name) = function.name_ref() {
+                    self.mark_object(name);
+                }
+                for value in function.chunk().constants() {
+                    self.mark_value(*value);
+                }
+            },
+            ObjKind::Closure(closure) => {
+                self.mark_object(closure.function_ref());
+                for upvalue in closure.upvalues() {
+                    self.mark_object(*upvalue);
+                }
+            },
+            ObjKind::Upvalue(upvalue) => {
+                if let UpvalueState::Closed(value) = upvalue.state() {
+                    self.mark_value(value);
+                }
+            },
+            ObjKind::Class(class) => {
+                self.mark_object(class.name_ref());
+                for (name, method) in class.methods() {
+                    self.mark_object(name);
+                    self.mark_value(method);
+                }
+            },
+            ObjKind::Instance(instance) => {
+                self.mark_object(instance.class_ref());
+                for (name, value) in instance.fields() {
+                    self.mark_object(name);
+                    self.mark_value(value);
+                }
+            },
+            ObjKind::BoundMethod(bound) => {
+                self.mark_value(bound.receiver());
+                self.mark_object(bound.method_ref());
+            },
+            ObjKind::Native(native) => {
+                self.mark_object(native.name_ref());
+            },
+        }
+    }
+    pub fn sweep(&mut self) {
+        let mut freed = 0;
+        self.objects.retain(|obj| {
+            if obj.is_marked() {
+                obj.borrow().set_marked(false);
+                return true;
+            }
+            #[cfg(feature = "logGc")]
+            let _ = writeln!(self.log.borrow_mut(), "{:?} free type {}", obj, obj.borrow().type_name());
+            freed += obj.borrow().size();
+            drop(unsafe { obj.into_box() });
+            false
+        });
+        self.bytes_allocated -= freed;
+        self.next_gc = self.bytes_allocated * self.grow_factor;
+    }
+    #[cfg(feature = "logGc")]
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+    #[cfg(feature = "logGc")]
+    pub fn next_gc(&self) -> usize {
+        self.next_gc
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        for obj in self.objects.drain(..) {
+            drop(unsafe { obj.into_box() });
+        }
+    }
+}
@@ -0,0 +1,13 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::values::Value;
+use crate::errors::RuntimeError;
+use crate::virtual_machine::VM;
+
+/// Seconds since the Unix epoch, as a fractional number.
+pub fn clock(_vm: &mut VM, _args: &[Value]) -> Result<Value, RuntimeError> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| RuntimeError::new("System clock is before the Unix epoch."))?;
+    Ok(Value::create_number(elapsed.as_secs_f64()))
+}
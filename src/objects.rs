@@ -0,0 +1,360 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::ptr::NonNull;
+
+use crate::chunk::Chunk;
+use crate::errors::RuntimeError;
+use crate::values::Value;
+use crate::virtual_machine::VM;
+
+pub enum ObjKind {
+    String(ObjString),
+    Function(ObjFunction),
+    Closure(ObjClosure),
+    Upvalue(ObjUpvalue),
+    Class(ObjClass),
+    Instance(ObjInstance),
+    BoundMethod(ObjBoundMethod),
+    Native(ObjNative),
+}
+
+pub struct Obj {
+    is_marked: Cell<bool>,
+    kind: ObjKind,
+}
+
+impl Obj {
+    pub fn new(kind: ObjKind) -> Obj {
+        Obj { is_marked: Cell::new(false), kind }
+    }
+    pub fn kind(&self) -> &ObjKind {
+        &self.kind
+    }
+    pub fn is_marked(&self) -> bool {
+        self.is_marked.get()
+    }
+    pub fn set_marked(&self, value: bool) {
+        self.is_marked.set(value);
+    }
+    #[cfg(feature = "logGc")]
+    pub fn type_name(&self) -> &'static str {
+        match &self.kind {
+            ObjKind::String(_) => "string",
+            ObjKind::Function(_) => "function",
+            ObjKind::Closure(_) => "closure",
+            ObjKind::Upvalue(_) => "upvalue",
+            ObjKind::Class(_) => "class",
+            ObjKind::Instance(_) => "instance",
+            ObjKind::BoundMethod(_) => "bound method",
+            ObjKind::Native(_) => "native",
+        }
+    }
+    /// Approximate number of bytes owned by the object, used to pace the
+    /// garbage collector. Only the size at allocation is counted, so later
+    /// growth of instance fields and class methods is not tracked.
+    pub fn size(&self) -> usize {
+        let extra = match &self.kind {
+            ObjKind::String(s) => s.chars.capacity(),
+            ObjKind::Function(f) => f.chunk.byte_size(),
+            ObjKind::Closure(c) => c.upvalues.capacity() * std::mem::size_of::<ObjRef>(),
+            ObjKind::Upvalue(_) => 0,
+            ObjKind::Class(_) => 0,
+            ObjKind::Instance(_) => 0,
+            ObjKind::BoundMethod(_) => 0,
+            ObjKind::Native(_) => 0,
+        };
+        std::mem::size_of::<Obj>() + extra
+    }
+}
+
+impl fmt::Display for Obj {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ObjKind::String(s) => write!(f, "{}", s.as_str()),
+            ObjKind::Function(function) => function.fmt(f),
+            ObjKind::Closure(c) => c.function().fmt(f),
+            ObjKind::Upvalue(_) => write!(f, "upvalue"),
+            ObjKind::Class(c) => write!(f, "{}", c.name()),
+            ObjKind::Instance(i) => write!(f, "{} instance", i.class().name()),
+            ObjKind::BoundMethod(b) => b.method().function().fmt(f),
+            ObjKind::Native(_) => write!(f, "<native fn>"),
+        }
+    }
+}
+
+pub struct ObjString {
+    chars: String,
+}
+
+impl ObjString {
+    pub fn new(chars: String) -> ObjString {
+        ObjString { chars }
+    }
+    pub fn as_str(&self) -> &str {
+        &self.chars
+    }
+}
+
+pub struct ObjFunction {
+    arity: usize,
+    upvalue_count: usize,
+    chunk: Chunk,
+    name: Option<ObjRef>,
+}
+
+impl ObjFunction {
+    pub fn new(arity: usize, upvalue_count: usize, chunk: Chunk, name: Option<ObjRef>) -> ObjFunction {
+        ObjFunction { arity, upvalue_count, chunk, name }
+    }
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+    pub fn upvalue_count(&self) -> usize {
+        self.upvalue_count
+    }
+    pub fn chunk(&self) -> &Chunk {
+        &self.chunk
+    }
+    pub fn name_ref(&self) -> Option<ObjRef> {
+        self.name
+    }
+    pub fn name(&self) -> &str {
+        match self.name.as_ref().and_then(|name| name.as_string()) {
+            Some(name) => name.as_str(),
+            None => "script",
+        }
+    }
+}
+
+impl fmt::Display for ObjFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name {
+            Some(_) => write!(f, "<fn {}>", self.name()),
+            None => write!(f, "<script>"),
+        }
+    }
+}
+
+pub struct ObjClosure {
+    function: ObjRef,
+    upvalues: Vec<ObjRef>,
+}
+
+impl ObjClosure {
+    pub fn new(function: ObjRef, upvalues: Vec<ObjRef>) -> ObjClosure {
+        ObjClosure { function, upvalues }
+    }
+    pub fn function_ref(&self) -> ObjRef {
+        self.function
+    }
+    pub fn upvalues(&self) -> &[ObjRef] {
+        &self.upvalues
+    }
+    pub fn function(&self) -> &ObjFunction {
+        self.function.as_function().expect("Internal error, closure without function")
+    }
+    pub fn upvalue(&self, idx: usize) -> ObjRef {
+        self.upvalues[idx]
+    }
+}
+
+/// A captured variable. While the variable is still on the VM stack the
+/// upvalue is open and refers to its slot; once the slot goes away the
+/// value is moved into the upvalue itself.
+#[derive(Clone, Copy)]
+pub enum UpvalueState {
+    Open(usize),
+    Closed(Value),
+}
+
+pub struct ObjUpvalue {
+    state: Cell<UpvalueState>,
+}
+
+impl ObjUpvalue {
+    pub fn new(slot: usize) -> ObjUpvalue {
+        ObjUpvalue { state: Cell::new(UpvalueState::Open(slot)) }
+    }
+    pub fn state(&self) -> UpvalueState {
+        self.state.get()
+    }
+    pub fn set_state(&self, state: UpvalueState) {
+        self.state.set(state);
+    }
+}
+
+pub struct ObjClass {
+    name: ObjRef,
+    methods: RefCell<HashMap<ObjRef, Value>>,
+}
+
+impl ObjClass {
+    pub fn new(name: ObjRef) -> ObjClass {
+        ObjClass { name, methods: RefCell::new(HashMap::new()) }
+    }
+    pub fn get_method(&self, name: ObjRef) -> Option<Value> {
+        self.methods.borrow().get(&name).copied()
+    }
+    pub fn set_method(&self, name: ObjRef, method: Value) {
+        self.methods.borrow_mut().insert(name, method);
+    }
+    pub fn methods(&self) -> Vec<(ObjRef, Value)> {
+        self.methods.borrow().iter().map(|(k, v)| (*k, *v)).collect()
+    }
+    pub fn name_ref(&self) -> ObjRef {
+        self.name
+    }
+    pub fn name(&self) -> &str {
+        self.name.as_string().map_or("", |s| s.as_str())
+    }
+}
+
+pub struct ObjInstance {
+    class: ObjRef,
+    fields: RefCell<HashMap<ObjRef, Value>>,
+}
+
+impl ObjInstance {
+    pub fn new(class: ObjRef) -> ObjInstance {
+        ObjInstance { class, fields: RefCell::new(HashMap::new()) }
+    }
+    pub fn class_ref(&self) -> ObjRef {
+        self.class
+    }
+    pub fn class(&self) -> &ObjClass {
+        self.class.as_class().expect("Internal error, instance without class")
+    }
+    pub fn get_field(&self, name: ObjRef) -> Option<Value> {
+        self.fields.borrow().get(&name).copied()
+    }
+    pub fn set_field(&self, name: ObjRef, value: Value) {
+        self.fields.borrow_mut().insert(name, value);
+    }
+    pub fn fields(&self) -> Vec<(ObjRef, Value)> {
+        self.fields.borrow().iter().map(|(k, v)| (*k, *v)).collect()
+    }
+}
+
+pub struct ObjBoundMethod {
+    receiver: Value,
+    method: ObjRef,
+}
+
+impl ObjBoundMethod {
+    pub fn new(receiver: Value, method: ObjRef) -> ObjBoundMethod {
+        ObjBoundMethod { receiver, method }
+    }
+    pub fn receiver(&self) -> Value {
+        self.receiver
+    }
+    pub fn method_ref(&self) -> ObjRef {
+        self.method
+    }
+    pub fn method(&self) -> &ObjClosure {
+        self.method.as_closure().expect("Internal error, bound method without closure")
+    }
+}
+
+/// Signature of a host function callable from Lox. The arguments, and any
+/// value the native creates, are only valid until the native returns.
+pub type NativeFn = fn(&mut VM, &[Value]) -> Result<Value, RuntimeError>;
+
+pub struct ObjNative {
+    name: ObjRef,
+    arity: usize,
+    function: NativeFn,
+}
+
+impl ObjNative {
+    pub fn new(name: ObjRef, arity: usize, function: NativeFn) -> ObjNative {
+        ObjNative { name, arity, function }
+    }
+    pub fn name_ref(&self) -> ObjRef {
+        self.name
+    }
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+    pub fn function(&self) -> NativeFn {
+        self.function
+    }
+}
+
+/// Handle to an object owned by the VM heap. Handles are plain pointers so
+/// they can be copied around inside `Value`s; the heap is responsible for
+/// keeping the object alive as long as a handle to it may be used.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ObjRef(NonNull<Obj>);
+
+impl ObjRef {
+    pub fn from_box(obj: Box<Obj>) -> ObjRef {
+        ObjRef(NonNull::from(Box::leak(obj)))
+    }
+    /// # Safety
+    /// The handle must have been created by `from_box` and not freed before.
+    pub unsafe fn into_box(self) -> Box<Obj> {
+        Box::from_raw(self.0.as_ptr())
+    }
+    #[cfg(feature = "nanBoxing")]
+    pub fn to_bits(self) -> u64 {
+        self.0.as_ptr() as usize as u64
+    }
+    /// # Safety
+    /// `bits` must come from `to_bits` on a handle that is still live.
+    #[cfg(feature = "nanBoxing")]
+    pub unsafe fn from_bits(bits: u64) -> ObjRef {
+        ObjRef(NonNull::new_unchecked(bits as usize as *mut Obj))
+    }
+    pub fn borrow(&self) -> &Obj {
+        unsafe { self.0.as_ref() }
+    }
+    pub fn is_marked(&self) -> bool {
+        self.borrow().is_marked()
+    }
+    pub fn is_string(&self) -> bool {
+        matches!(self.borrow().kind(), ObjKind::String(_))
+    }
+    pub fn as_string(&self) -> Option<&ObjString> {
+        match self.borrow().kind() {
+            ObjKind::String(s) => Some(s),
+            _ => None,
+        }
+    }
+    pub fn as_function(&self) -> Option<&ObjFunction> {
+        match self.borrow().kind() {
+            ObjKind::Function(f) => Some(f),
+            _ => None,
+        }
+    }
+    pub fn as_closure(&self) -> Option<&ObjClosure> {
+        match self.borrow().kind() {
+            ObjKind::Closure(c) => Some(c),
+            _ => None,
+        }
+    }
+    pub fn as_upvalue(&self) -> Option<&ObjUpvalue> {
+        match self.borrow().kind() {
+            ObjKind::Upvalue(u) => Some(u),
+            _ => None,
+        }
+    }
+    pub fn as_class(&self) -> Option<&ObjClass> {
+        match self.borrow().kind() {
+            ObjKind::Class(c) => Some(c),
+            _ => None,
+        }
+    }
+    pub fn as_instance(&self) -> Option<&ObjInstance> {
+        match self.borrow().kind() {
+            ObjKind::Instance(i) => Some(i),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ObjRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.borrow().fmt(f)
+    }
+}
@@ -1,82 +1,91 @@
-pub struct Scanner {
-    buffer: Vec<char>,
+use std::ops::Range;
+
+/// Splits a source string into tokens on demand. Tokens borrow their
+/// lexemes from the source, so scanning never copies it.
+pub struct Scanner<'a> {
+    source: &'a str,
     start: usize,
     current: usize,
     line: usize,
     column: usize,
+    start_line: usize,
+    start_column: usize,
 }
 
-impl Scanner {
+impl<'a> Scanner<'a> {
 
-    pub fn init(source: &str) -> Scanner {
+    pub fn init(source: &'a str) -> Scanner<'a> {
         Scanner { 
-            buffer: source.chars().collect(),
+            source,
             start: 0,
             current: 0,
             line: 1,
-            column: 1
+            column: 1,
+            start_line: 1,
+            start_column: 1,
         }
     }
 
-    pub fn scan_token(&mut self) -> Token {
+    pub fn scan_token(&mut self) -> Token<'a> {
         self.skip_whitespace();
         self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.column;
         if self.is_at_end() {
             return self.make_token(TokenType::EOF);
         }
         let c = self.advance();
         match c {
-            '(' => return self.make_token(TokenType::LeftParen),
-            ')' => return self.make_token(TokenType::RightParen),
-            '{' => return self.make_token(TokenType::LeftBrace),
-            '}' => return self.make_token(TokenType::RightBrace),
-            ';' => return self.make_token(TokenType::SemiColon),
-            ',' => return self.make_token(TokenType::Comma),
-            '.' => return self.make_token(TokenType::Dot),
-            '-' => return self.make_token(TokenType::Minus),
-            '+' => return self.make_token(TokenType::Plus),
-            '/' => return self.make_token(TokenType::Slash),
-            '*' => return self.make_token(TokenType::Star),
+            '(' => self.make_token(TokenType::LeftParen),
+            ')' => self.make_token(TokenType::RightParen),
+            '{' => self.make_token(TokenType::LeftBrace),
+            '}' => self.make_token(TokenType::RightBrace),
+            ';' => self.make_token(TokenType::SemiColon),
+            ',' => self.make_token(TokenType::Comma),
+            '.' => self.make_token(TokenType::Dot),
+            '-' => self.make_token(TokenType::Minus),
+            '+' => self.make_token(TokenType::Plus),
+            '/' => self.make_token(TokenType::Slash),
+            '*' => self.make_token(TokenType::Star),
             '!' => {
                 if self.match_char('=') {
-                    return self.make_token(TokenType::BangEqual);
+                    self.make_token(TokenType::BangEqual)
                 } else {
-                    return self.make_token(TokenType::Bang);
+                    self.make_token(TokenType::Bang)
                 }
             },
             '=' => {
                 if self.match_char('=') {
-                    return self.make_token(TokenType::EqualEqual);
+                    self.make_token(TokenType::EqualEqual)
                 } else {
-                    return self.make_token(TokenType::Equal);
+                    self.make_token(TokenType::Equal)
                 }
             },
             '<' => {
                 if self.match_char('=') {
-                    return self.make_token(TokenType::LessEqual);
+                    self.make_token(TokenType::LessEqual)
                 } else {
-                    return self.make_token(TokenType::Less);
+                    self.make_token(TokenType::Less)
                 }
             }
             '>' => {
                 if self.match_char('=') {
-                    return self.make_token(TokenType::GreateEqual);
+                    self.make_token(TokenType::GreateEqual)
                 } else {
-                    return self.make_token(TokenType::Greater);
+                    self.make_token(TokenType::Greater)
                 }
             }
-            '"' => return self.string(),
+            '"' => self.string(),
             _ => {
-                if c.is_digit(10) {
+                if c.is_ascii_digit() {
                     return self.number();
                 }
                 if Self::identifier_first(c) {
                     return self.identifier();
                 }
-                return self.error_token("Unexpected character.")
+                self.error_token("Unexpected character.")
             },
         }
-        // self.error_token("Unexpected character.")
     }
 
     fn identifier_first(c: char) -> bool {
@@ -87,7 +96,7 @@ impl Scanner {
         c.is_alphanumeric() || c == '_'
     }
 
-    fn identifier(&mut self) -> Token {
+    fn identifier(&mut self) -> Token<'a> {
         while Self::identifier_rest(self.peek()) {
             let _ = self.advance();
         }
@@ -95,72 +104,69 @@ impl Scanner {
         self.make_token(tt)
     }
 
-    fn identifier_type(&mut self) -> TokenType {
-        match self.buffer[self.start] {
-            'a' => self.check_keyword(1, 2, vec!['n', 'd'], TokenType::And),
-            'c' => self.check_keyword(1, 4, vec!['l', 'a', 's', 's'], TokenType::Class),
-            'e' => self.check_keyword(1, 3, vec!['l', 's', 'e'], TokenType::Else),
-            'f' => {
+    fn identifier_type(&self) -> TokenType {
+        let bytes = self.source.as_bytes();
+        match bytes[self.start] {
+            b'a' => self.check_keyword(1, "nd", TokenType::And),
+            b'c' => self.check_keyword(1, "lass", TokenType::Class),
+            b'e' => self.check_keyword(1, "lse", TokenType::Else),
+            b'f' => {
                 if self.current - self.start > 1 {
-                    match self.buffer[self.start + 1] {
-                        'a' => self.check_keyword(2, 3, vec!['l', 's', 'e'], TokenType::False),
-                        'o' => self.check_keyword(2, 1, vec!['r'], TokenType::For),
-                        'u' => self.check_keyword(2, 1, vec!['n'], TokenType::Fun),
+                    match bytes[self.start + 1] {
+                        b'a' => self.check_keyword(2, "lse", TokenType::False),
+                        b'o' => self.check_keyword(2, "r", TokenType::For),
+                        b'u' => self.check_keyword(2, "n", TokenType::Fun),
                         _ => TokenType::Identifier
                     }
                 } else {
                     TokenType::Identifier
                 }
             }
-            'i' => self.check_keyword(1, 1, vec!['f'], TokenType::If),
-            'n' => self.check_keyword(1, 2, vec!['i', 'l'], TokenType::Nil),
-            'o' => self.check_keyword(1, 1, vec!['r'], TokenType::Or),
-            'p' => self.check_keyword(1, 4, vec!['r', 'i', 'n', 't'], TokenType::Print),
-            'r' => self.check_keyword(1, 5, vec!['e', 't', 'u', 'r', 'n'], TokenType::Return),
-            's' => self.check_keyword(1, 4, vec!['u', 'p', 'e', 'r'], TokenType::Super),
-            't' => {
+            b'i' => self.check_keyword(1, "f", TokenType::If),
+            b'n' => self.check_keyword(1, "il", TokenType::Nil),
+            b'o' => self.check_keyword(1, "r", TokenType::Or),
+            b'p' => self.check_keyword(1, "rint", TokenType::Print),
+            b'r' => self.check_keyword(1, "eturn", TokenType::Return),
+            b's' => self.check_keyword(1, "uper", TokenType::Super),
+            b't' => {
                 if self.current - self.start > 1 {
-                    match self.buffer[self.start + 1] {
-                        'h' => self.check_keyword(2, 2, vec!['i', 's'], TokenType::This),
-                        'r' => self.check_keyword(2, 2, vec!['u', 'e'], TokenType::True),
+                    match bytes[self.start + 1] {
+                        b'h' => self.check_keyword(2, "is", TokenType::This),
+                        b'r' => self.check_keyword(2, "ue", TokenType::True),
                         _ => TokenType::Identifier
                     }
                 } else {
                     TokenType::Identifier
                 }
             }
-            'v' => self.check_keyword(1 ,2, vec!['a', 'r'], TokenType::Var),
-            'w' => self.check_keyword(1, 4, vec!['h', 'i', 'l', 'e'], TokenType::While),
+            b'v' => self.check_keyword(1, "ar", TokenType::Var),
+            b'w' => self.check_keyword(1, "hile", TokenType::While),
             _ => TokenType::Identifier,
         }
     }
 
-    fn check_keyword(&mut self, offset: usize, length: usize, chars: Vec<char>, ttype: TokenType) -> TokenType {
-        if self.current - self.start != offset + length {
-            return TokenType::Identifier
-        }
-        for idx in 0..length {
-            if chars[idx] != self.buffer[self.start + offset + idx] {
-                return TokenType::Identifier
-            }
+    fn check_keyword(&self, offset: usize, rest: &str, ttype: TokenType) -> TokenType {
+        if &self.source.as_bytes()[self.start + offset..self.current] == rest.as_bytes() {
+            ttype
+        } else {
+            TokenType::Identifier
         }
-        ttype
     }
 
-    fn number(&mut self) -> Token {
-        while self.peek().is_digit(10) {
+    fn number(&mut self) -> Token<'a> {
+        while self.peek().is_ascii_digit() {
             let _ = self.advance();
         }
-        if self.peek() == '.' && self.peek_next(1).is_digit(10) {
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
             let _ = self.advance();
-            while self.peek().is_digit(10) {
+            while self.peek().is_ascii_digit() {
                 let _ = self.advance();
             }    
         }
-        return self.make_token(TokenType::Number)
+        self.make_token(TokenType::Number)
     }
 
-    fn string(&mut self) -> Token {
+    fn string(&mut self) -> Token<'a> {
         while !self.is_at_end() && self.peek() != '"' {
             let _ = self.advance();
         }
@@ -176,7 +182,7 @@ impl Scanner {
             if self.peek().is_whitespace() {
                 let _ = self.advance();
             } else if self.peek() == '/' {
-                if self.peek_next(1) == '/' {
+                if self.peek_next() == '/' {
                     while self.peek() != '\n' && !self.is_at_end() {
                         let _ = self.advance();
                     }
@@ -189,19 +195,24 @@ impl Scanner {
         }
     }
 
-    fn peek(&self) -> char {
-        if self.is_at_end() {
-            '\0'
-        } else {
-            self.buffer[self.current]
+    /// Decodes the character at byte offset `at`, or `'\0'` past the end.
+    fn char_at(&self, at: usize) -> char {
+        match self.source.as_bytes().get(at) {
+            None => '\0',
+            Some(&byte) if byte.is_ascii() => byte as char,
+            Some(_) => self.source[at..].chars().next().unwrap_or('\0'),
         }
     }
 
-    fn peek_next(&self, steps: usize) -> char {
-        if self.current + steps >= self.buffer.len() {
+    fn peek(&self) -> char {
+        self.char_at(self.current)
+    }
+
+    fn peek_next(&self) -> char {
+        if self.is_at_end() {
             '\0'
         } else {
-            self.buffer[self.current + steps]
+            self.char_at(self.current + self.peek().len_utf8())
         }
     }
 
@@ -213,60 +224,63 @@ impl Scanner {
         true
     }
 
-    pub fn advance(&mut self) -> char {
-        let c = self.buffer[self.current];
+    fn advance(&mut self) -> char {
+        let c = self.peek();
         match c {
             '\n' => {
                 self.line += 1;
                 self.column = 1;
             },
-            '\t' => {
-                self.column += 4;
-            }
             _ => {
                 self.column += 1;
             }
         }
-        self.current += 1;
+        self.current += c.len_utf8();
         c
     }
 
     fn is_at_end(&self) -> bool {
-        self.current >= self.buffer.len()
+        self.current >= self.source.len()
     }
 
-    fn make_token(&self, ttype: TokenType) -> Token {
-        let mut s = String::new();
-        for i in self.start..self.current {
-            s.push(self.buffer[i]);
-        }
+    fn make_token(&self, ttype: TokenType) -> Token<'a> {
         Token {
             token_type: ttype,
-            the_string: s,
-            line: self.line,
-            column: self.column,
+            lexeme: &self.source[self.start..self.current],
+            line: self.start_line,
+            column: self.start_column,
+            start: self.start,
+            length: self.current - self.start,
         }
     }
 
-    fn error_token(&self, message: &str) -> Token {
+    fn error_token(&self, message: &'static str) -> Token<'a> {
         Token {
             token_type: TokenType::Error,
-            the_string: message.to_string(),
-            line: self.line,
-            column: self.column,
+            lexeme: message,
+            line: self.start_line,
+            column: self.start_column,
+            start: self.start,
+            length: self.current - self.start,
         }
     }
 }
 
-#[derive(Clone)]
-pub struct Token {
+/// A lexeme of the source. `line` and `column` locate its first character
+/// (columns count characters, so a tab or a multi-byte character is one
+/// column), and `start` and `length` give its span as byte offsets into the
+/// source. Error tokens carry the message in place of the lexeme.
+#[derive(Clone, Copy)]
+pub struct Token<'a> {
     token_type: TokenType,
-    the_string: String,
+    lexeme: &'a str,
     line: usize,
     column: usize,
+    start: usize,
+    length: usize,
 }
 
-impl Token {
+impl<'a> Token<'a> {
     pub fn ttype(&self) -> TokenType {
         self.token_type
     }
@@ -276,15 +290,30 @@ impl Token {
     pub fn column(&self) -> usize {
         self.column
     }
-    pub fn as_str(&self) -> &str {
-        &self.the_string
+    pub fn span(&self) -> Range<usize> {
+        self.start..self.start + self.length
     }
-    pub fn create_dummy() -> Token {
+    pub fn as_str(&self) -> &'a str {
+        self.lexeme
+    }
+    pub fn create_dummy() -> Token<'a> {
         Token {
             token_type: TokenType::Dummy,
-            the_string: String::new(),
+            lexeme: "",
+            line: 0,
+            column: 0,
+            start: 0,
+            length: 0,
+        }
+    }
+    pub fn create_synthetic(text: &'a str) -> Token<'a> {
+        Token {
+            token_type: TokenType::Identifier,
+            lexeme: text,
             line: 0,
             column: 0,
+            start: 0,
+            length: 0,
         }
     }
 }
@@ -334,7 +363,10 @@ pub enum TokenType {
     Var,
     While,
     // Special.
-    Error, EOF, Dummy,
+    Error,
+    #[allow(clippy::upper_case_acronyms)]
+    EOF,
+    Dummy,
 }
 
 impl std::fmt::Display for TokenType {
@@ -384,4 +416,41 @@ impl std::fmt::Display for TokenType {
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_borrow_lexemes_with_character_columns() {
+        let source = "var café = \"ünï\";\n  ?";
+        let mut scanner = Scanner::init(source);
+        let mut tokens = vec![];
+        loop {
+            let token = scanner.scan_token();
+            if token.ttype() == TokenType::EOF {
+                break;
+            }
+            tokens.push(token);
+        }
+        let summary: Vec<_> = tokens.iter().map(|t| (t.ttype(), t.as_str(), t.line(), t.column())).collect();
+        assert_eq!(summary, [
+            (TokenType::Var, "var", 1, 1),
+            (TokenType::Identifier, "café", 1, 5),
+            (TokenType::Equal, "=", 1, 10),
+            (TokenType::String, "\"ünï\"", 1, 12),
+            (TokenType::SemiColon, ";", 1, 17),
+            (TokenType::Error, "Unexpected character.", 2, 3),
+        ]);
+        assert_eq!(&source[tokens[1].span()], "café");
+    }
+
+    #[test]
+    fn scanning_past_the_end_keeps_returning_eof() {
+        let mut scanner = Scanner::init("");
+        for _ in 0..3 {
+            assert_eq!(scanner.scan_token().ttype(), TokenType::EOF);
+        }
+    }
+}
@@ -1,99 +1,318 @@
+use std::fmt;
 use std::ops;
 
-use crate::virtual_machine::InterpretError;
+use crate::objects::ObjRef;
+use crate::errors::RuntimeError;
 
-#[derive(Clone, Copy, Debug)]
-pub enum Value {
+/// A Lox value as stored on the VM stack and in the heap. The layout is
+/// private so it can change with the `nanBoxing` feature.
+///
+/// A value may refer to a heap object, which the VM frees once no script
+/// data refers to it. The host only sees values inside a native call and
+/// must not keep them after the native returns; use `HostValue` for data
+/// that has to outlive the call. The object handle itself stays private:
+///
+/// ```compile_fail
+/// let _ = rlox::Value::try_as_object;
+/// ```
+#[cfg(not(feature = "nanBoxing"))]
+#[derive(Clone, Copy)]
+pub struct Value(Repr);
+
+#[cfg(not(feature = "nanBoxing"))]
+#[derive(Clone, Copy)]
+enum Repr {
     Number(f64),
     Boolean(bool),
     Nil,
+    Object(ObjRef),
 }
 
+#[cfg(not(feature = "nanBoxing"))]
 impl Value {
     pub fn create_number(value: f64) -> Value {
-        Value::Number(value)
+        Value(Repr::Number(value))
     }
     pub fn create_boolean(value: bool) -> Value {
-        Value::Boolean(value)
+        Value(Repr::Boolean(value))
     }
     pub fn create_nil() -> Value {
-        Value::Nil
+        Value(Repr::Nil)
+    }
+    pub(crate) fn create_object(obj: ObjRef) -> Value {
+        Value(Repr::Object(obj))
     }
     pub fn is_boolean(&self) -> bool {
-        match self {
-            Value::Boolean(_) => true,
-            _ => false,
-        }
+        matches!(self.0, Repr::Boolean(_))
     }
     pub fn is_nil(&self) -> bool {
-        match self {
-            Value::Nil => true,
-            _ => false,
+        matches!(self.0, Repr::Nil)
+    }
+    pub fn is_number(&self) -> bool {
+        matches!(self.0, Repr::Number(_))
+    }
+    pub fn is_object(&self) -> bool {
+        matches!(self.0, Repr::Object(_))
+    }
+    pub fn try_as_boolean(&self) -> Result<bool, RuntimeError> {
+        if let Repr::Boolean(value) = self.0 {
+            Ok(value)
+        } else {
+            Err(RuntimeError::new("Value is not a boolean."))
+        }
+    }
+    pub fn try_as_number(&self) -> Result<f64, RuntimeError> {
+        if let Repr::Number(value) = self.0 {
+            Ok(value)
+        } else {
+            Err(RuntimeError::new("Value is not a number."))
         }
     }
+    pub(crate) fn try_as_object(&self) -> Result<ObjRef, RuntimeError> {
+        if let Repr::Object(obj) = self.0 {
+            Ok(obj)
+        } else {
+            Err(RuntimeError::new("Value is not an object."))
+        }
+    }
+}
+
+/// A Lox value packed into the bits of a double, as in clox. Anything that is
+/// not a quiet NaN is a number; quiet NaNs carry nil and the booleans in
+/// their low bits, or an object pointer when the sign bit is set. As in the
+/// default layout, the host must not keep values past a native call.
+#[cfg(feature = "nanBoxing")]
+#[derive(Clone, Copy)]
+pub struct Value(u64);
+
+#[cfg(feature = "nanBoxing")]
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+#[cfg(feature = "nanBoxing")]
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+#[cfg(feature = "nanBoxing")]
+const TAG_NIL: u64 = 1;
+#[cfg(feature = "nanBoxing")]
+const TAG_FALSE: u64 = 2;
+#[cfg(feature = "nanBoxing")]
+const TAG_TRUE: u64 = 3;
+
+#[cfg(feature = "nanBoxing")]
+impl Value {
+    pub fn create_number(value: f64) -> Value {
+        Value(value.to_bits())
+    }
+    pub fn create_boolean(value: bool) -> Value {
+        Value(QNAN | if value { TAG_TRUE } else { TAG_FALSE })
+    }
+    pub fn create_nil() -> Value {
+        Value(QNAN | TAG_NIL)
+    }
+    pub(crate) fn create_object(obj: ObjRef) -> Value {
+        Value(SIGN_BIT | QNAN | obj.to_bits())
+    }
+    pub fn is_boolean(&self) -> bool {
+        self.0 | 1 == QNAN | TAG_TRUE
+    }
+    pub fn is_nil(&self) -> bool {
+        self.0 == QNAN | TAG_NIL
+    }
     pub fn is_number(&self) -> bool {
-        match self {
-            Value::Number(_) => true,
-            _ => false,
+        self.0 & QNAN != QNAN
+    }
+    pub fn is_object(&self) -> bool {
+        self.0 & (SIGN_BIT | QNAN) == SIGN_BIT | QNAN
+    }
+    pub fn try_as_boolean(&self) -> Result<bool, RuntimeError> {
+        if self.is_boolean() {
+            Ok(self.0 == QNAN | TAG_TRUE)
+        } else {
+            Err(RuntimeError::new("Value is not a boolean."))
         }
     }
-    pub fn try_as_boolean(&self) -> Result<bool, InterpretError> {
-        if let Value::Boolean(value) = self {
-            Ok(*value)
+    pub fn try_as_number(&self) -> Result<f64, RuntimeError> {
+        if self.is_number() {
+            Ok(f64::from_bits(self.0))
         } else {
-            Err(InterpretError::RuntimeError)
+            Err(RuntimeError::new("Value is not a number."))
         }
     }
-    pub fn try_as_number(&self) -> Result<f64, InterpretError> {
-        if let Value::Number(value) = self {
-            Ok(*value)
+    pub(crate) fn try_as_object(&self) -> Result<ObjRef, RuntimeError> {
+        if self.is_object() {
+            // Safety: only `create_object` sets both the sign and QNAN bits.
+            Ok(unsafe { ObjRef::from_bits(self.0 & !(SIGN_BIT | QNAN)) })
         } else {
-            Err(InterpretError::RuntimeError)
+            Err(RuntimeError::new("Value is not an object."))
         }
     }
 }
 
+impl Value {
+    pub fn is_string(&self) -> bool {
+        self.try_as_object().is_ok_and(|obj| obj.is_string())
+    }
+    /// Copies the characters out of a string value.
+    pub fn try_as_string(&self) -> Result<String, RuntimeError> {
+        self.try_as_object().ok()
+            .and_then(|obj| obj.as_string().map(|s| s.as_str().to_string()))
+            .ok_or_else(|| RuntimeError::new("Value is not a string."))
+    }
+}
+
 impl From<f64> for Value {
     fn from(f: f64) -> Value {
         Value::create_number(f)
     }
 }
 
-impl Value {
-    pub fn print(&self) {
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::create_boolean(b)
+    }
+}
+
+/// A Lox value copied out of the VM. Unlike `Value`, it owns its data, so
+/// the host can keep it for as long as it likes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostValue {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    /// Any other object, as `print` shows it (`<fn f>`, `A instance`, ...).
+    /// Only the text is copied, so `VM::set_global` refuses it.
+    Object(String),
+}
+
+impl From<f64> for HostValue {
+    fn from(f: f64) -> HostValue {
+        HostValue::Number(f)
+    }
+}
+
+impl From<bool> for HostValue {
+    fn from(b: bool) -> HostValue {
+        HostValue::Boolean(b)
+    }
+}
+
+impl From<&str> for HostValue {
+    fn from(s: &str) -> HostValue {
+        HostValue::String(s.to_string())
+    }
+}
+
+impl From<String> for HostValue {
+    fn from(s: String) -> HostValue {
+        HostValue::String(s)
+    }
+}
+
+impl fmt::Display for HostValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Value::Number(value) => print!("{}", value),
-            Value::Boolean(value) => print!("{}", value),
-            Value::Nil => print!("nil"),
+            HostValue::Nil => write!(f, "nil"),
+            HostValue::Boolean(value) => write!(f, "{}", value),
+            HostValue::Number(value) => write!(f, "{}", format_number(*value)),
+            HostValue::String(text) | HostValue::Object(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// Significant digits printed for numbers, as with C's `%g`.
+const NUMBER_PRECISION: i32 = 6;
+
+/// Formats a number the way clox's `printf("%g")` does: six significant
+/// digits, no trailing zeros, and an exponent for very large or small
+/// magnitudes.
+fn format_number(value: f64) -> String {
+    if value.is_nan() {
+        return String::from("nan");
+    }
+    if value.is_infinite() {
+        return String::from(if value > 0.0 { "inf" } else { "-inf" });
+    }
+    if value == 0.0 {
+        return String::from(if value.is_sign_negative() { "-0" } else { "0" });
+    }
+    // Rounding can carry into the next power of ten, so the style is chosen
+    // from the exponent of the rounded value.
+    let scientific = format!("{:.*e}", (NUMBER_PRECISION - 1) as usize, value);
+    let (mantissa, exponent) = scientific.split_once('e').expect("Internal error, malformed exponent");
+    let exponent: i32 = exponent.parse().expect("Internal error, malformed exponent");
+    if !(-4..NUMBER_PRECISION).contains(&exponent) {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim_fraction(mantissa), sign, exponent.abs())
+    } else {
+        let decimals = (NUMBER_PRECISION - 1 - exponent) as usize;
+        trim_fraction(&format!("{:.*}", decimals, value)).to_string()
+    }
+}
+
+fn trim_fraction(digits: &str) -> &str {
+    if digits.contains('.') {
+        digits.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        digits
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Ok(value) = self.try_as_number() {
+            write!(f, "{}", format_number(value))
+        } else if let Ok(value) = self.try_as_boolean() {
+            write!(f, "{}", value)
+        } else if let Ok(obj) = self.try_as_object() {
+            write!(f, "{}", obj)
+        } else {
+            write!(f, "nil")
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_number() {
+            write!(f, "Number({})", self)
+        } else if self.is_boolean() {
+            write!(f, "Boolean({})", self)
+        } else if self.is_string() {
+            write!(f, "Object({:?})", self.to_string())
+        } else if self.is_object() {
+            write!(f, "Object({})", self)
+        } else {
+            write!(f, "Nil")
+        }
+    }
+}
+
+impl Value {
+    /// Copies the value out of the heap.
+    pub fn to_host(self) -> HostValue {
+        if let Ok(value) = self.try_as_number() {
+            HostValue::Number(value)
+        } else if let Ok(value) = self.try_as_boolean() {
+            HostValue::Boolean(value)
+        } else if let Ok(text) = self.try_as_string() {
+            HostValue::String(text)
+        } else if self.is_object() {
+            HostValue::Object(self.to_string())
+        } else {
+            HostValue::Nil
         }
-        
     }
     pub fn is_falsey(&self) -> bool {
         self.is_nil() || (self.is_boolean() && !self.try_as_boolean().unwrap_or(false))
     }
     pub fn is_equal_to(&self, rhs: &Value) -> bool {
-        match self {
-            Value::Number(f1) => {
-                if let Ok(f2) = rhs.try_as_number() {
-                    *f1 == f2
-                } else {
-                    false
-                }
-            },
-            Value::Boolean(b1) => {
-                if let Ok(b2) = rhs.try_as_boolean() {
-                    *b1 == b2
-                } else {
-                    false
-                }
-            },
-            Value::Nil => {
-                if let Value::Nil = rhs {
-                    true
-                } else {
-                    false
-                }
-            },
+        if let (Ok(f1), Ok(f2)) = (self.try_as_number(), rhs.try_as_number()) {
+            f1 == f2
+        } else if let (Ok(b1), Ok(b2)) = (self.try_as_boolean(), rhs.try_as_boolean()) {
+            b1 == b2
+        } else if let (Ok(o1), Ok(o2)) = (self.try_as_object(), rhs.try_as_object()) {
+            o1 == o2
+        } else {
+            self.is_nil() && rhs.is_nil()
         }
     }
     pub fn is_greater_than(&self, rhs: &Value) -> bool {
@@ -194,4 +413,50 @@ impl ops::Div for Value {
             Value::create_nil()
         }
    }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Heap;
+
+    #[test]
+    fn numbers_display_like_printf_g() {
+        let cases = [
+            (7.0, "7"),
+            (-0.0, "-0"),
+            (1e21, "1e+21"),
+            (123456.0, "123456"),
+            (1234567.0, "1.23457e+06"),
+            (0.000123, "0.000123"),
+            (1.5e-7, "1.5e-07"),
+            (f64::NAN, "nan"),
+            (f64::NEG_INFINITY, "-inf"),
+        ];
+        for (number, expected) in cases {
+            assert_eq!(Value::create_number(number).to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn values_keep_their_type_and_payload() {
+        let mut heap = Heap::new();
+        let string = Value::create_object(heap.alloc_string(String::from("boxed")));
+        let other = Value::create_object(heap.alloc_string(String::from("boxed")));
+        let values = [Value::from(-1.5), Value::from(f64::NAN), Value::from(true), Value::from(false), Value::create_nil(), string];
+        assert_eq!(values.map(|v| v.is_number()), [true, true, false, false, false, false]);
+        assert_eq!(values.map(|v| v.is_boolean()), [false, false, true, true, false, false]);
+        assert_eq!(values.map(|v| v.is_nil()), [false, false, false, false, true, false]);
+        assert_eq!(values.map(|v| v.is_string()), [false, false, false, false, false, true]);
+        assert_eq!(values.map(|v| v.is_falsey()), [false, false, false, true, true, false]);
+        assert_eq!(values[0].try_as_number().ok(), Some(-1.5));
+        assert_eq!(values[3].try_as_boolean().ok(), Some(false));
+        assert_eq!(string.to_host(), HostValue::from("boxed"));
+        assert!(!values[1].is_equal_to(&values[1]));
+        assert!(string.is_equal_to(&string));
+        assert!(!string.is_equal_to(&other));
+        if cfg!(feature = "nanBoxing") {
+            assert_eq!(std::mem::size_of::<Value>(), 8);
+        }
+    }
+}
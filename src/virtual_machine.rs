@@ -1,42 +1,209 @@
-#[cfg(any(feature = "traceExecution"))]
+#[cfg(feature = "traceExecution")]
 use crate::disassembler;
-use crate::{chunk, compiler::Parser, values};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::rc::Rc;
 
-pub enum InterpretError {
-    CompileError,
-    RuntimeError,
-}
+use crate::{chunk, compiler::Parser, errors::{CompileError, InterpretError, RuntimeError, TraceFrame}, memory::Heap, natives, objects::{NativeFn, ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjKind, ObjNative, ObjRef, ObjUpvalue, UpvalueState}, values::{self, HostValue}};
 
-const MAX_STACK_SIZE: usize = 255;
+/// Destination for diagnostics. It is shared because the heap logs to the
+/// same place as the VM when the `logGc` feature is on.
+pub type Sink = Rc<RefCell<dyn Write>>;
 
-pub struct VM {
+const MAX_FRAMES: usize = 64;
+const MAX_STACK_SIZE: usize = MAX_FRAMES * 256;
+
+struct CallFrame {
+    closure: ObjRef,
     ip: usize,
+    slots: usize,
+}
+
+/// A Lox interpreter. Globals persist between calls to `interpret`, so a
+/// host can run several scripts against the same state.
+///
+/// Heap objects never leave the VM as raw handles; the host sees them only
+/// as `Value`s inside a native call or as `HostValue` copies. The methods
+/// that hand out handles stay private to the crate:
+///
+/// ```compile_fail
+/// let _ = rlox::VM::intern;
+/// ```
+///
+/// ```compile_fail
+/// let _ = rlox::VM::alloc_function;
+/// ```
+///
+/// ```compile_fail
+/// let _ = rlox::VM::add_compiler_root;
+/// ```
+pub struct VM {
+    frames: Vec<CallFrame>,
     stack: Vec<values::Value>,
+    heap: Heap,
+    strings: HashMap<String, ObjRef>,
+    globals: HashMap<ObjRef, values::Value>,
+    open_upvalues: Vec<ObjRef>,
+    compiler_roots: HashSet<ObjRef>,
+    native_roots: Vec<ObjRef>,
+    init_string: ObjRef,
+    source_name: String,
+    output: Box<dyn Write>,
+    diagnostics: Sink,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
      pub fn new() -> Self {
-        Self { ip: 0 , stack: vec![] }
+        let mut heap = Heap::new();
+        let init_string = heap.alloc_string("init".to_string());
+        let strings = HashMap::from([("init".to_string(), init_string)]);
+        let diagnostics: Sink = Rc::new(RefCell::new(io::stderr()));
+        #[cfg(feature = "logGc")]
+        heap.set_log(diagnostics.clone());
+        let mut vm = Self {
+            frames: vec![],
+            stack: vec![],
+            heap,
+            strings,
+            globals: HashMap::new(),
+            open_upvalues: vec![],
+            compiler_roots: HashSet::new(),
+            native_roots: vec![],
+            init_string,
+            source_name: String::from("script"),
+            output: Box::new(io::stdout()),
+            diagnostics,
+        };
+        vm.define_native("clock", 0, natives::clock);
+        vm
+    }
+    /// Sends the output of `print` statements to `output` instead of stdout.
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
+    /// Sends the logs of the `traceExecution`, `dumpChunk` and `logGc`
+    /// features to `diagnostics` instead of stderr.
+    pub fn set_diagnostics(&mut self, diagnostics: impl Write + 'static) {
+        self.diagnostics = Rc::new(RefCell::new(diagnostics));
+        #[cfg(feature = "logGc")]
+        self.heap.set_log(self.diagnostics.clone());
+    }
+    #[cfg(feature = "dumpChunk")]
+    pub fn diagnostics(&self) -> Sink {
+        self.diagnostics.clone()
+    }
+    /// Sets the heap size, in bytes, at which the next garbage collection
+    /// runs. Later collections are scheduled by `set_gc_grow_factor`.
+    pub fn set_gc_threshold(&mut self, bytes: usize) {
+        self.heap.set_next_gc(bytes);
+    }
+    /// After each collection, schedules the next one for when the heap
+    /// reaches `factor` times the bytes that survived. Defaults to 2.
+    pub fn set_gc_grow_factor(&mut self, factor: usize) {
+        self.heap.set_grow_factor(factor);
+    }
+    /// Name given to the source most recently passed to `interpret_named`.
+    pub fn source_name(&self) -> &str {
+        &self.source_name
+    }
+    pub fn get_global(&self, name: &str) -> Option<HostValue> {
+        let name = self.strings.get(name)?;
+        self.globals.get(name).map(|value| value.to_host())
+    }
+    /// Defines or overwrites a global. Objects other than strings only exist
+    /// inside the VM, so `HostValue::Object` is refused.
+    pub fn set_global(&mut self, name: &str, value: impl Into<HostValue>) -> Result<(), RuntimeError> {
+        let value = match value.into() {
+            HostValue::Nil => values::Value::create_nil(),
+            HostValue::Boolean(value) => values::Value::create_boolean(value),
+            HostValue::Number(value) => values::Value::create_number(value),
+            HostValue::String(text) => values::Value::create_object(self.intern(&text)),
+            HostValue::Object(text) => return Err(RuntimeError::new(&format!("Cannot pass object '{}' into the VM.", text))),
+        };
+        // Keep the value rooted while the name is interned.
+        self.stack.push(value);
+        let name = self.intern(name);
+        self.stack.pop();
+        self.globals.insert(name, value);
+        Ok(())
+    }
+    /// Creates a string for a native to return. Like the native's
+    /// arguments, it is only valid until the native returns.
+    pub fn create_string(&mut self, chars: &str) -> values::Value {
+        let obj = self.intern(chars);
+        self.native_roots.push(obj);
+        values::Value::create_object(obj)
+    }
+    /// Registers a host function as a global callable from Lox.
+    pub fn define_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        let name = self.intern(name);
+        self.stack.push(values::Value::create_object(name));
+        let native = self.alloc(ObjKind::Native(ObjNative::new(name, arity, function)));
+        self.stack.pop();
+        self.globals.insert(name, values::Value::create_object(native));
     }
     pub fn interpret(&mut self, source: &str) -> Result<(), InterpretError> {
-        let mut chunk = chunk::Chunk::new();
-        let mut parser = Parser::new();
-        if let Err(e) = parser.compile(source, &mut chunk) {
-            return Err(e);
+        self.interpret_named("script", source)
+    }
+    /// Like `interpret`, but records `name` (usually a file path) as the
+    /// origin of the source for error reporting.
+    ///
+    /// Natives can't use this to run more code while a script is running;
+    /// the nested call fails with a runtime error and leaves the running
+    /// script untouched.
+    pub fn interpret_named(&mut self, name: &str, source: &str) -> Result<(), InterpretError> {
+        if !self.frames.is_empty() {
+            return Err(RuntimeError::new("Can't interpret while a script is running.").into());
         }
-        self.ip = 0;
-        self.run(&chunk)?;
-        Ok(())
+        let function = self.compile(name, source)?;
+        self.push(values::Value::create_object(function))?;
+        let closure = self.alloc_closure(ObjClosure::new(function, vec![]));
+        self.pop()?;
+        self.push(values::Value::create_object(closure))?;
+        self.call(closure, 0)?;
+        self.run()
+    }
+    /// Compiles `source` into the top-level function without running it.
+    /// The function is not rooted, so it must be used before the next
+    /// allocation.
+    pub(crate) fn compile(&mut self, name: &str, source: &str) -> Result<ObjRef, Vec<CompileError>> {
+        self.source_name = name.to_string();
+        let mut parser = Parser::new();
+        let compiled = parser.compile(source, self);
+        self.compiler_roots.clear();
+        compiled
+    }
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("Internal error, no active call frame")
+    }
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("Internal error, no active call frame")
     }
     fn read_byte(&mut self, chunk: &chunk::Chunk) -> chunk::Byte {
-        let result = chunk.read_code(self.ip);
-        self.ip += 1;
+        let frame = self.frame_mut();
+        let result = chunk.read_code(frame.ip);
+        frame.ip += 1;
         result
     }
     fn read_const(&mut self, chunk: &chunk::Chunk) -> values::Value {
         let idx = usize::from(self.read_byte(chunk));
         chunk.read_constant(idx)
     }
+    fn read_short(&mut self, chunk: &chunk::Chunk) -> usize {
+        let hi = usize::from(self.read_byte(chunk));
+        let lo = usize::from(self.read_byte(chunk));
+        hi << 8 | lo
+    }
+    fn read_string(&mut self, chunk: &chunk::Chunk) -> ObjRef {
+        self.read_const(chunk).try_as_object().expect("Internal error, name constant is not a string")
+    }
     fn read_long_const(&mut self, chunk: &chunk::Chunk) -> values::Value {
         let i1 = usize::from(self.read_byte(chunk));
         let i2 = usize::from(self.read_byte(chunk));
@@ -44,17 +211,22 @@ impl VM {
         let idx = i1 << 16 | i2 << 8 | i3;
         chunk.read_constant(idx)
     }
-    fn run(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+    fn run(&mut self) -> Result<(), InterpretError> {
         loop {
+            let closure = self.frame().closure;
+            let chunk = closure.as_closure().expect("Internal error, frame without closure").function().chunk();
             #[cfg(feature = "traceExecution")]
-            self.traceStack();
-            #[cfg(feature = "traceExecution")]
-            disassembler::dump_instruction(chunk, self.ip);
+            self.trace_execution(chunk);
             if let Ok(instruction) = chunk::OpCode::try_from(self.read_byte(chunk)) {
                 match instruction {
                     chunk::OpCode::OpConstant => self.execute_constant(chunk)?,
                     chunk::OpCode::OpConstantLong => self.execute_long_constant(chunk)?,
-                    chunk::OpCode::OpReturn => return self.execute_return(chunk),
+                    chunk::OpCode::OpReturn => {
+                        self.execute_return(chunk)?;
+                        if self.frames.is_empty() {
+                            return Ok(());
+                        }
+                    },
                     chunk::OpCode::OpNegate => self.execute_negate(chunk)?,
                     chunk::OpCode::OpAdd => self.execute_add(chunk)?,
                     chunk::OpCode::OpSubtract => self.execute_subtract(chunk)?,
@@ -67,26 +239,48 @@ impl VM {
                     chunk::OpCode::OpEqual => self.execute_equal(chunk)?,
                     chunk::OpCode::OpLess => self.execute_less(chunk)?,
                     chunk::OpCode::OpGreater => self.execute_greater(chunk)?,
+                    chunk::OpCode::OpPrint => self.execute_print(chunk)?,
+                    chunk::OpCode::OpPop => self.execute_pop(chunk)?,
+                    chunk::OpCode::OpPopN => self.execute_pop_n(chunk)?,
+                    chunk::OpCode::OpGetLocal => self.execute_get_local(chunk)?,
+                    chunk::OpCode::OpSetLocal => self.execute_set_local(chunk)?,
+                    chunk::OpCode::OpDefineGlobal => self.execute_define_global(chunk)?,
+                    chunk::OpCode::OpGetGlobal => self.execute_get_global(chunk)?,
+                    chunk::OpCode::OpSetGlobal => self.execute_set_global(chunk)?,
+                    chunk::OpCode::OpJump => self.execute_jump(chunk)?,
+                    chunk::OpCode::OpJumpIfFalse => self.execute_jump_if_false(chunk)?,
+                    chunk::OpCode::OpLoop => self.execute_loop(chunk)?,
+                    chunk::OpCode::OpCall => self.execute_call(chunk)?,
+                    chunk::OpCode::OpClosure => self.execute_closure(chunk)?,
+                    chunk::OpCode::OpGetUpvalue => self.execute_get_upvalue(chunk)?,
+                    chunk::OpCode::OpSetUpvalue => self.execute_set_upvalue(chunk)?,
+                    chunk::OpCode::OpCloseUpvalue => self.execute_close_upvalue(chunk)?,
+                    chunk::OpCode::OpClass => self.execute_class(chunk)?,
+                    chunk::OpCode::OpGetProperty => self.execute_get_property(chunk)?,
+                    chunk::OpCode::OpSetProperty => self.execute_set_property(chunk)?,
+                    chunk::OpCode::OpMethod => self.execute_method(chunk)?,
+                    chunk::OpCode::OpInvoke => self.execute_invoke(chunk)?,
+                    chunk::OpCode::OpInherit => self.execute_inherit(chunk)?,
+                    chunk::OpCode::OpGetSuper => self.execute_get_super(chunk)?,
+                    chunk::OpCode::OpSuperInvoke => self.execute_super_invoke(chunk)?,
                 }    
             }
         }
     }
-    fn execute_less(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+    fn execute_less(&mut self, _chunk: &chunk::Chunk) -> Result<(), InterpretError> {
         let b = self.pop()?;
         let a = self.pop()?;
         if !a.is_number() || !b.is_number() {
-            self.runtime_error(chunk, "Operand must be a number.");
-            return Err(InterpretError::RuntimeError);            
+            return Err(self.runtime_error("Operand must be a number."));
         }
         self.push(values::Value::create_boolean(a.is_less_than(&b)))?;
         Ok(())
     }
-    fn execute_greater(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+    fn execute_greater(&mut self, _chunk: &chunk::Chunk) -> Result<(), InterpretError> {
         let b = self.pop()?;
         let a = self.pop()?;
         if !a.is_number() || !b.is_number() {
-            self.runtime_error(chunk, "Operand must be a number.");
-            return Err(InterpretError::RuntimeError);            
+            return Err(self.runtime_error("Operand must be a number."));
         }
         self.push(values::Value::create_boolean(a.is_greater_than(&b)))?;
         Ok(())
@@ -125,65 +319,482 @@ impl VM {
         Ok(())
     }
     fn execute_return(&mut self, _chunk: &chunk::Chunk) -> Result<(), InterpretError> {
-        self.pop()?.print();
-        println!("");
+        let result = self.pop()?;
+        let frame = self.frames.pop().expect("Internal error, no active call frame");
+        self.close_upvalues(frame.slots);
+        self.stack.truncate(frame.slots);
+        if !self.frames.is_empty() {
+            self.push(result)?;
+        }
+        Ok(())
+    }
+    fn execute_call(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let arg_count = usize::from(self.read_byte(chunk));
+        self.call_value(self.peek_stack(arg_count), arg_count)
+    }
+    fn call_value(&mut self, callee: values::Value, arg_count: usize) -> Result<(), InterpretError> {
+        if let Ok(obj) = callee.try_as_object() {
+            match obj.borrow().kind() {
+                ObjKind::Closure(_) => return self.call(obj, arg_count),
+                ObjKind::Native(native) => return self.call_native(native, arg_count),
+                ObjKind::BoundMethod(bound) => {
+                    let slot = self.stack.len() - arg_count - 1;
+                    self.stack[slot] = bound.receiver();
+                    return self.call(bound.method_ref(), arg_count);
+                },
+                ObjKind::Class(class) => {
+                    let instance = self.alloc(ObjKind::Instance(ObjInstance::new(obj)));
+                    let slot = self.stack.len() - arg_count - 1;
+                    self.stack[slot] = values::Value::create_object(instance);
+                    if let Some(initializer) = class.get_method(self.init_string) {
+                        return self.call(initializer.try_as_object().expect("Internal error, initializer is not a closure"), arg_count);
+                    }
+                    if arg_count != 0 {
+                        let msg = format!("Expected 0 arguments but got {}.", arg_count);
+                        return Err(self.runtime_error(&msg));
+                    }
+                    return Ok(());
+                },
+                _ => {},
+            }
+        }
+        Err(self.runtime_error("Can only call functions and classes."))
+    }
+    fn call(&mut self, closure: ObjRef, arg_count: usize) -> Result<(), InterpretError> {
+        let arity = closure.as_closure().map_or(0, |c| c.function().arity());
+        if arg_count != arity {
+            let msg = format!("Expected {} arguments but got {}.", arity, arg_count);
+            return Err(self.runtime_error(&msg));
+        }
+        if self.frames.len() == MAX_FRAMES {
+            return Err(self.runtime_error("Stack overflow."));
+        }
+        let slots = self.stack.len() - arg_count - 1;
+        self.frames.push(CallFrame { closure, ip: 0, slots });
+        Ok(())
+    }
+    fn call_native(&mut self, native: &ObjNative, arg_count: usize) -> Result<(), InterpretError> {
+        if arg_count != native.arity() {
+            let msg = format!("Expected {} arguments but got {}.", native.arity(), arg_count);
+            return Err(self.runtime_error(&msg));
+        }
+        let args = self.stack[self.stack.len() - arg_count..].to_vec();
+        match (native.function())(self, &args) {
+            Ok(result) => {
+                self.stack.truncate(self.stack.len() - arg_count - 1);
+                let pushed = self.push(result);
+                self.native_roots.clear();
+                pushed
+            },
+            Err(error) => Err(self.raise(error)),
+        }
+    }
+    fn execute_closure(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let function = self.read_const(chunk).try_as_object().expect("Internal error, closure constant is not a function");
+        let upvalue_count = function.as_function().map_or(0, |f| f.upvalue_count());
+        let mut upvalues = Vec::with_capacity(upvalue_count);
+        for _ in 0..upvalue_count {
+            let is_local = usize::from(self.read_byte(chunk)) == 1;
+            let index = usize::from(self.read_byte(chunk));
+            if is_local {
+                let slot = self.frame().slots + index;
+                upvalues.push(self.capture_upvalue(slot));
+            } else {
+                let enclosing = self.frame().closure;
+                upvalues.push(enclosing.as_closure().expect("Internal error, frame without closure").upvalue(index));
+            }
+        }
+        let closure = self.alloc_closure(ObjClosure::new(function, upvalues));
+        self.push(values::Value::create_object(closure))?;
+        Ok(())
+    }
+    fn execute_class(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let name = self.read_string(chunk);
+        let class = self.alloc(ObjKind::Class(ObjClass::new(name)));
+        self.push(values::Value::create_object(class))
+    }
+    fn execute_get_property(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let name = self.read_string(chunk);
+        let receiver = self.peek_stack(0);
+        let Some(instance) = receiver.try_as_object().ok().filter(|obj| obj.as_instance().is_some()) else {
+            return Err(self.runtime_error("Only instances have properties."));
+        };
+        if let Some(value) = instance.as_instance().and_then(|i| i.get_field(name)) {
+            self.pop()?;
+            return self.push(value);
+        }
+        let class = instance.as_instance().map(|i| i.class_ref());
+        self.bind_method(class, name)
+    }
+    fn bind_method(&mut self, class: Option<ObjRef>, name: ObjRef) -> Result<(), InterpretError> {
+        let Some(method) = class.and_then(|c| c.as_class().and_then(|c| c.get_method(name))) else {
+            return self.undefined_property(name);
+        };
+        let bound = ObjBoundMethod::new(self.peek_stack(0), method.try_as_object().expect("Internal error, method is not a closure"));
+        let bound = self.alloc(ObjKind::BoundMethod(bound));
+        self.pop()?;
+        self.push(values::Value::create_object(bound))
+    }
+    fn undefined_property(&mut self, name: ObjRef) -> Result<(), InterpretError> {
+        let msg = format!("Undefined property '{}'.", name.as_string().map_or("", |s| s.as_str()));
+        Err(self.runtime_error(&msg))
+    }
+    fn execute_method(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let name = self.read_string(chunk);
+        let method = self.peek_stack(0);
+        let class = self.peek_stack(1).try_as_object().expect("Internal error, method defined outside a class");
+        if let Some(class) = class.as_class() {
+            class.set_method(name, method);
+        }
+        self.pop()?;
+        Ok(())
+    }
+    fn execute_invoke(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let name = self.read_string(chunk);
+        let arg_count = usize::from(self.read_byte(chunk));
+        self.invoke(name, arg_count)
+    }
+    fn invoke(&mut self, name: ObjRef, arg_count: usize) -> Result<(), InterpretError> {
+        let receiver = self.peek_stack(arg_count).try_as_object().ok();
+        let Some(instance) = receiver.as_ref().and_then(|obj| obj.as_instance()) else {
+            return Err(self.runtime_error("Only instances have methods."));
+        };
+        if let Some(field) = instance.get_field(name) {
+            let slot = self.stack.len() - arg_count - 1;
+            self.stack[slot] = field;
+            return self.call_value(field, arg_count);
+        }
+        self.invoke_from_class(instance.class_ref(), name, arg_count)
+    }
+    fn invoke_from_class(&mut self, class: ObjRef, name: ObjRef, arg_count: usize) -> Result<(), InterpretError> {
+        match class.as_class().and_then(|c| c.get_method(name)) {
+            Some(method) => self.call(method.try_as_object().expect("Internal error, method is not a closure"), arg_count),
+            None => self.undefined_property(name),
+        }
+    }
+    fn execute_inherit(&mut self, _chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let superclass = self.peek_stack(1).try_as_object().ok();
+        let Some(superclass) = superclass.as_ref().and_then(|obj| obj.as_class()) else {
+            return Err(self.runtime_error("Superclass must be a class."));
+        };
+        // Methods are copied down when the class is created, so inherited
+        // calls cost the same as calls to the class's own methods.
+        let subclass = self.peek_stack(0).try_as_object().expect("Internal error, subclass is not a class");
+        if let Some(subclass) = subclass.as_class() {
+            for (name, method) in superclass.methods() {
+                subclass.set_method(name, method);
+            }
+        }
+        self.pop()?;
+        Ok(())
+    }
+    fn execute_get_super(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let name = self.read_string(chunk);
+        let superclass = self.pop()?.try_as_object().expect("Internal error, superclass is not a class");
+        self.bind_method(Some(superclass), name)
+    }
+    fn execute_super_invoke(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let name = self.read_string(chunk);
+        let arg_count = usize::from(self.read_byte(chunk));
+        let superclass = self.pop()?.try_as_object().expect("Internal error, superclass is not a class");
+        self.invoke_from_class(superclass, name, arg_count)
+    }
+    fn execute_set_property(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let name = self.read_string(chunk);
+        let receiver = self.peek_stack(1);
+        let Some(instance) = receiver.try_as_object().ok().filter(|obj| obj.as_instance().is_some()) else {
+            return Err(self.runtime_error("Only instances have fields."));
+        };
+        let value = self.pop()?;
+        if let Some(instance) = instance.as_instance() {
+            instance.set_field(name, value);
+        }
+        self.pop()?;
+        self.push(value)
+    }
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let position = self.open_upvalues.partition_point(|upvalue| Self::open_slot(*upvalue) < slot);
+        if let Some(existing) = self.open_upvalues.get(position) {
+            if Self::open_slot(*existing) == slot {
+                return *existing;
+            }
+        }
+        let upvalue = self.alloc(ObjKind::Upvalue(ObjUpvalue::new(slot)));
+        self.open_upvalues.insert(position, upvalue);
+        upvalue
+    }
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let slot = Self::open_slot(*upvalue);
+            if slot < last {
+                break;
+            }
+            if let Some(upvalue) = upvalue.as_upvalue() {
+                upvalue.set_state(UpvalueState::Closed(self.stack[slot]));
+            }
+            self.open_upvalues.pop();
+        }
+    }
+    fn open_slot(upvalue: ObjRef) -> usize {
+        match upvalue.as_upvalue().map(|u| u.state()) {
+            Some(UpvalueState::Open(slot)) => slot,
+            _ => panic!("Internal error, open upvalue list holds a closed upvalue"),
+        }
+    }
+    fn frame_upvalue(&self, idx: usize) -> ObjRef {
+        let closure = self.frame().closure;
+        closure.as_closure().expect("Internal error, frame without closure").upvalue(idx)
+    }
+    fn execute_get_upvalue(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let idx = usize::from(self.read_byte(chunk));
+        let upvalue = self.frame_upvalue(idx);
+        let value = match upvalue.as_upvalue().map(|u| u.state()) {
+            Some(UpvalueState::Open(slot)) => self.stack[slot],
+            Some(UpvalueState::Closed(value)) => value,
+            None => return Err(self.runtime_error("Internal error, upvalue is not an upvalue.")),
+        };
+        self.push(value)
+    }
+    fn execute_set_upvalue(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let idx = usize::from(self.read_byte(chunk));
+        let upvalue = self.frame_upvalue(idx);
+        let value = self.peek_stack(0);
+        if let Some(upvalue) = upvalue.as_upvalue() {
+            match upvalue.state() {
+                UpvalueState::Open(slot) => self.stack[slot] = value,
+                UpvalueState::Closed(_) => upvalue.set_state(UpvalueState::Closed(value)),
+            }
+        }
+        Ok(())
+    }
+    fn execute_close_upvalue(&mut self, _chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        self.close_upvalues(self.stack.len() - 1);
+        self.pop()?;
+        Ok(())
+    }
+    fn execute_print(&mut self, _chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let value = self.pop()?;
+        if writeln!(self.output, "{}", value).is_err() {
+            return Err(self.runtime_error("Failed to write output."));
+        }
+        Ok(())
+    }
+    fn execute_pop(&mut self, _chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        self.pop()?;
+        Ok(())
+    }
+    fn execute_pop_n(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let count = usize::from(self.read_byte(chunk));
+        for _ in 0..count {
+            self.pop()?;
+        }
+        Ok(())
+    }
+    fn execute_get_local(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let slot = self.frame().slots + usize::from(self.read_byte(chunk));
+        self.push(self.stack[slot])?;
+        Ok(())
+    }
+    fn execute_set_local(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let slot = self.frame().slots + usize::from(self.read_byte(chunk));
+        self.stack[slot] = self.peek_stack(0);
         Ok(())
     }
-    fn execute_negate(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+    fn execute_jump(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let offset = self.read_short(chunk);
+        self.frame_mut().ip += offset;
+        Ok(())
+    }
+    fn execute_jump_if_false(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let offset = self.read_short(chunk);
+        if self.peek_stack(0).is_falsey() {
+            self.frame_mut().ip += offset;
+        }
+        Ok(())
+    }
+    fn execute_loop(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let offset = self.read_short(chunk);
+        self.frame_mut().ip -= offset;
+        Ok(())
+    }
+    fn execute_define_global(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let name = self.read_string(chunk);
+        let value = self.peek_stack(0);
+        self.globals.insert(name, value);
+        self.pop()?;
+        Ok(())
+    }
+    fn execute_get_global(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let name = self.read_string(chunk);
+        if let Some(value) = self.globals.get(&name) {
+            self.push(*value)?;
+            Ok(())
+        } else {
+            self.undefined_variable(name)
+        }
+    }
+    fn execute_set_global(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        let name = self.read_string(chunk);
+        let value = self.peek_stack(0);
+        if let Some(slot) = self.globals.get_mut(&name) {
+            *slot = value;
+            Ok(())
+        } else {
+            self.undefined_variable(name)
+        }
+    }
+    fn undefined_variable(&mut self, name: ObjRef) -> Result<(), InterpretError> {
+        let msg = format!("Undefined variable '{}'.", name.as_string().map_or("", |s| s.as_str()));
+        Err(self.runtime_error(&msg))
+    }
+    fn execute_negate(&mut self, _chunk: &chunk::Chunk) -> Result<(), InterpretError> {
         if self.peek_stack(0).is_number() {
             let value = self.pop()?;
             self.push(-value)?;
         } else {
-            self.runtime_error(chunk, "Operand must be a number.");
-            return Err(InterpretError::RuntimeError);
+            return Err(self.runtime_error("Operand must be a number."));
         }
         Ok(())
     }
     fn peek_stack(&self, idx: usize) -> values::Value {
         self.stack[self.stack.len() - idx - 1]
     }
-    fn runtime_error(&mut self, chunk: &chunk::Chunk, msg: &str) {
-        eprintln!("{}", msg);
-        let instruction = self.ip - 1;
-        let line = chunk.read_line(instruction);
-        eprintln!("[line {}] in script\n", line);
+    fn runtime_error(&mut self, msg: &str) -> InterpretError {
+        self.raise(RuntimeError::new(msg))
+    }
+    /// Attaches the current call stack to `error` and resets the VM so the
+    /// next `interpret` starts from a clean state.
+    fn raise(&mut self, error: RuntimeError) -> InterpretError {
+        let trace = self.frames.iter().rev().filter_map(|frame| {
+            let function = frame.closure.as_closure()?.function();
+            let line = function.chunk().read_line(frame.ip.saturating_sub(1));
+            let name = function.name_ref().map(|_| function.name());
+            Some(TraceFrame::new(name, line))
+        }).collect();
+        // Closures that escaped still need the values they captured.
+        self.close_upvalues(0);
         self.stack.clear();
+        self.frames.clear();
+        self.native_roots.clear();
+        InterpretError::RuntimeError(error.with_trace(&self.source_name, trace))
     }
 
-    fn execute_add(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+    fn alloc(&mut self, kind: ObjKind) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc(kind)
+    }
+    pub(crate) fn alloc_function(&mut self, function: ObjFunction) -> ObjRef {
+        self.alloc(ObjKind::Function(function))
+    }
+    fn alloc_closure(&mut self, closure: ObjClosure) -> ObjRef {
+        self.alloc(ObjKind::Closure(closure))
+    }
+    /// Keeps an object created by the compiler alive until compilation is
+    /// over; until then it is only reachable from chunks under construction.
+    /// Rooting the same object again is a no-op.
+    pub(crate) fn add_compiler_root(&mut self, obj: ObjRef) {
+        self.compiler_roots.insert(obj);
+    }
+    fn collect_garbage(&mut self) {
+        #[cfg(feature = "logGc")]
+        let before = self.heap.bytes_allocated();
+        #[cfg(feature = "logGc")]
+        let _ = writeln!(self.diagnostics.borrow_mut(), "-- gc begin");
+        self.mark_roots();
+        self.heap.trace_references();
+        self.strings.retain(|_, obj| obj.is_marked());
+        self.heap.sweep();
+        #[cfg(feature = "logGc")]
+        {
+            let after = self.heap.bytes_allocated();
+            let mut log = self.diagnostics.borrow_mut();
+            let _ = writeln!(log, "-- gc end");
+            let _ = writeln!(log, "   collected {} bytes (from {} to {}) next at {}", before - after, before, after, self.heap.next_gc());
+        }
+    }
+    fn mark_roots(&mut self) {
+        for value in &self.stack {
+            self.heap.mark_value(*value);
+        }
+        for frame in &self.frames {
+            self.heap.mark_object(frame.closure);
+        }
+        for upvalue in &self.open_upvalues {
+            self.heap.mark_object(*upvalue);
+        }
+        for (name, value) in &self.globals {
+            self.heap.mark_object(*name);
+            self.heap.mark_value(*value);
+        }
+        for obj in &self.compiler_roots {
+            self.heap.mark_object(*obj);
+        }
+        for obj in &self.native_roots {
+            self.heap.mark_object(*obj);
+        }
+        self.heap.mark_object(self.init_string);
+    }
+    /// Returns the one string object holding `chars`, allocating it on first
+    /// use. All strings go through here so equal strings are the same object.
+    pub(crate) fn intern(&mut self, chars: &str) -> ObjRef {
+        if let Some(obj) = self.strings.get(chars) {
+            return *obj;
+        }
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        let obj = self.heap.alloc_string(chars.to_string());
+        self.strings.insert(chars.to_string(), obj);
+        obj
+    }
+
+    fn execute_add(&mut self, _chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+        if self.peek_stack(0).is_string() && self.peek_stack(1).is_string() {
+            return self.concatenate();
+        }
         if !self.peek_stack(0).is_number() || !self.peek_stack(1).is_number() {
-            self.runtime_error(chunk, "Operands must be numbers.");
-            return Err(InterpretError::RuntimeError);
+            return Err(self.runtime_error("Operands must be two numbers or two strings."));
         }
         let arg1 = self.pop()?;
         let arg2 = self.pop()?;
         self.push(arg1 + arg2)?;
         Ok(())
     }
-    fn execute_subtract(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+    fn concatenate(&mut self) -> Result<(), InterpretError> {
+        let b = self.pop()?.try_as_object().expect("Internal error, concatenating a non-string");
+        let a = self.pop()?.try_as_object().expect("Internal error, concatenating a non-string");
+        let mut chars = String::new();
+        if let (Some(s1), Some(s2)) = (a.as_string(), b.as_string()) {
+            chars.push_str(s1.as_str());
+            chars.push_str(s2.as_str());
+        }
+        let result = self.intern(&chars);
+        self.push(values::Value::create_object(result))?;
+        Ok(())
+    }
+    fn execute_subtract(&mut self, _chunk: &chunk::Chunk) -> Result<(), InterpretError> {
         if !self.peek_stack(0).is_number() || !self.peek_stack(1).is_number() {
-            self.runtime_error(chunk, "Operands must be numbers.");
-            return Err(InterpretError::RuntimeError);
+            return Err(self.runtime_error("Operands must be numbers."));
         }
         let arg2 = self.pop()?;
         let arg1 = self.pop()?;
         self.push(arg1 - arg2)?;
         Ok(())
     }
-    fn execute_multiply(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+    fn execute_multiply(&mut self, _chunk: &chunk::Chunk) -> Result<(), InterpretError> {
         if !self.peek_stack(0).is_number() || !self.peek_stack(1).is_number() {
-            self.runtime_error(chunk, "Operands must be numbers.");
-            return Err(InterpretError::RuntimeError);
+            return Err(self.runtime_error("Operands must be numbers."));
         }
         let arg1 = self.pop()?;
         let arg2 = self.pop()?;
         self.push(arg1 * arg2)?;
         Ok(())
     }
-    fn execute_divide(&mut self, chunk: &chunk::Chunk) -> Result<(), InterpretError> {
+    fn execute_divide(&mut self, _chunk: &chunk::Chunk) -> Result<(), InterpretError> {
         if !self.peek_stack(0).is_number() || !self.peek_stack(1).is_number() {
-            self.runtime_error(chunk, "Operands must be numbers.");
-            return Err(InterpretError::RuntimeError);
+            return Err(self.runtime_error("Operands must be numbers."));
         }
         let arg2 = self.pop()?;
         let arg1 = self.pop()?;
@@ -192,26 +803,303 @@ impl VM {
     }
     fn push(&mut self, value: values::Value) -> Result<(), InterpretError> {
         if self.stack.len() >= MAX_STACK_SIZE {
-            return Err(InterpretError::RuntimeError);
+            return Err(self.runtime_error("Stack overflow."));
         }
         self.stack.push(value);
         Ok(())
     }
     fn pop(&mut self) -> Result<values::Value, InterpretError> {
-        if let Some(value) = self.stack.pop() {
-            Ok(value)
-        } else {
-            Err(InterpretError::RuntimeError)
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(self.runtime_error("Internal error, stack underflow.")),
         }
     }
     #[cfg(feature = "traceExecution")]
-    fn traceStack(&mut self) {
-        print!("        ");
-        for &value in &self.stack {
-            print!("[ ");
-            value.print();
-            print!(" ]");
+    fn trace_execution(&self, chunk: &chunk::Chunk) {
+        let mut out = self.diagnostics.borrow_mut();
+        let stack: String = self.stack.iter().map(|value| format!("[ {} ]", value)).collect();
+        let _ = writeln!(out, "        {}", stack);
+        let _ = disassembler::dump_instruction(&mut *out, chunk, self.frame().ip);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8_lossy(&self.0.borrow()).into_owned()
+        }
+    }
+
+    /// Looks a global up without interning its name.
+    fn global(vm: &VM, name: &str) -> Option<values::Value> {
+        vm.strings.get(name).and_then(|name| vm.globals.get(name)).copied()
+    }
+
+    fn compile(vm: &mut VM, source: &str) -> ObjRef {
+        let mut parser = Parser::new();
+        let function = parser.compile(source, vm);
+        assert!(function.is_ok());
+        function.ok().unwrap()
+    }
+
+    #[test]
+    fn intern_returns_same_object_for_equal_strings() {
+        let mut vm = VM::new();
+        let a = vm.intern("hello");
+        vm.stack.push(values::Value::create_object(a));
+        let b = vm.intern("hello");
+        let c = vm.intern("world");
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn identical_literals_share_one_allocation() {
+        let mut vm = VM::new();
+        let function = compile(&mut vm, "print \"abc\" == \"abc\";");
+        let chunk = function.as_function().unwrap().chunk();
+        let first = chunk.read_constant(0).try_as_object().ok();
+        let second = chunk.read_constant(1).try_as_object().ok();
+        assert!(first.is_some());
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn concatenation_result_is_interned() {
+        let mut vm = VM::new();
+        assert!(vm.interpret("var a = \"foobar\"; var b = \"foo\" + \"bar\";").is_ok());
+        let literal = global(&vm, "a").and_then(|v| v.try_as_object().ok());
+        let concatenated = global(&vm, "b").and_then(|v| v.try_as_object().ok());
+        assert!(literal.is_some());
+        assert_eq!(literal, concatenated);
+    }
+
+    #[test]
+    fn globals_persist_across_interpret_calls() {
+        let mut vm = VM::new();
+        assert!(vm.interpret("var answer = 41;").is_ok());
+        assert!(vm.interpret("answer = answer + 1;").is_ok());
+        assert_eq!(vm.get_global("answer"), Some(HostValue::Number(42.0)));
+    }
+
+    #[test]
+    fn jump_distance_overflow_is_compile_error() {
+        let mut vm = VM::new();
+        let body = "a = a;".repeat(14000);
+        let first_message = |vm: &mut VM, source: &str| {
+            let errors = Parser::new().compile(source, vm).err().unwrap_or_default();
+            errors.first().map(|error| error.message().to_string())
+        };
+        let jump = format!("{{ var a; if (true) {{ {} }} }}", body);
+        assert_eq!(first_message(&mut vm, &jump).as_deref(), Some("Too much code to jump over."));
+        let loop_ = format!("{{ var a; while (false) {{ {} }} }}", body);
+        assert_eq!(first_message(&mut vm, &loop_).as_deref(), Some("Loop body too large."));
+    }
+
+    #[test]
+    fn unbounded_recursion_is_runtime_error() {
+        let mut vm = VM::new();
+        let Err(InterpretError::RuntimeError(error)) = vm.interpret("fun f() { f(); } f();") else {
+            panic!("expected a runtime error");
+        };
+        assert_eq!(error.message(), "Stack overflow.");
+        assert!(vm.frames.is_empty());
+        assert!(vm.interpret("fun g(n) { return n; } var r = g(1);").is_ok());
+        assert_eq!(vm.get_global("r"), Some(HostValue::Number(1.0)));
+    }
+
+    #[test]
+    fn captured_variables_outlive_their_scope() {
+        let mut vm = VM::new();
+        let source = "fun counter() { var i = 0; fun inc() { i = i + 1; return i; } return inc; }
+                      var c = counter(); c(); var result = c();";
+        assert!(vm.interpret(source).is_ok());
+        assert!(vm.open_upvalues.is_empty());
+        assert_eq!(vm.get_global("result"), Some(HostValue::Number(2.0)));
+    }
+
+    #[test]
+    fn runtime_errors_close_escaped_upvalues() {
+        let mut vm = VM::new();
+        let source = "var f; fun outer() { var x = \"captured\"; fun inner() { return x; } f = inner; return nil + 1; } outer();";
+        assert!(vm.interpret(source).is_err());
+        assert!(vm.open_upvalues.is_empty());
+        assert!(vm.interpret("fun g(a, b, c) { return f(); } var r = g(\"wrong\", 2, 3);").is_ok());
+        assert_eq!(vm.get_global("r"), Some(HostValue::from("captured")));
+    }
+
+    #[test]
+    fn collection_frees_unreachable_strings() {
+        let mut vm = VM::new();
+        assert!(vm.interpret("var s = \"x\"; s = s + \"y\"; s = s + \"z\";").is_ok());
+        vm.collect_garbage();
+        assert!(!vm.strings.contains_key("xy"));
+        assert!(vm.strings.contains_key("xyz"));
+        assert!(vm.strings.contains_key("s"));
+    }
+
+    #[test]
+    fn gc_threshold_is_tunable() {
+        let mut vm = VM::new();
+        vm.set_gc_threshold(0);
+        vm.set_gc_grow_factor(1);
+        assert!(vm.interpret("var s = \"x\"; s = s + \"y\"; s = s + \"z\"; var t = \"u\" + \"v\";").is_ok());
+        assert!(!vm.strings.contains_key("xy"));
+        assert!(vm.strings.contains_key("xyz"));
+    }
+
+    #[test]
+    fn repeated_identifiers_are_rooted_once() {
+        let mut vm = VM::new();
+        compile(&mut vm, "var a; a; a; a; \"a\";");
+        assert_eq!(vm.compiler_roots.len(), 2);
+    }
+
+    #[test]
+    fn initializer_returns_the_instance() {
+        let mut vm = VM::new();
+        let source = "class P { init(x) { this.x = x; } get() { return this.x; } }
+                      var p = P(7); var same = p.init(8) == p; var x = p.get();";
+        assert!(vm.interpret(source).is_ok());
+        assert_eq!(vm.get_global("same"), Some(HostValue::Boolean(true)));
+        assert_eq!(vm.get_global("x"), Some(HostValue::Number(8.0)));
+    }
+
+    #[test]
+    fn super_calls_reach_the_superclass_method() {
+        let mut vm = VM::new();
+        let source = "class A { name() { return 1; } }
+                      class B < A { name() { return super.name() + 10; } }
+                      class C < B { get() { var m = super.name; return m(); } }
+                      var result = C().get() + C().name();";
+        assert!(vm.interpret(source).is_ok());
+        assert_eq!(vm.get_global("result"), Some(HostValue::Number(22.0)));
+    }
+
+    #[test]
+    fn inheriting_from_a_non_class_is_an_error() {
+        let mut vm = VM::new();
+        assert!(matches!(vm.interpret("class A < A {}"), Err(InterpretError::CompileError(_))));
+        assert!(matches!(vm.interpret("var x = 1; class A < x {}"), Err(InterpretError::RuntimeError(_))));
+    }
+
+    #[test]
+    fn natives_are_callable_from_lox() {
+        fn add(_vm: &mut VM, args: &[values::Value]) -> Result<values::Value, RuntimeError> {
+            match (args[0].try_as_number(), args[1].try_as_number()) {
+                (Ok(a), Ok(b)) => Ok(values::Value::from(a + b)),
+                _ => Err(RuntimeError::new("Operands must be numbers.")),
+            }
+        }
+        fn shout(vm: &mut VM, args: &[values::Value]) -> Result<values::Value, RuntimeError> {
+            let loud = vm.create_string(&format!("{}!", args[0]));
+            // Allocating again must not free the first string.
+            vm.create_string("ignored");
+            Ok(loud)
         }
-        println!("");
+        fn id(_vm: &mut VM, args: &[values::Value]) -> Result<values::Value, RuntimeError> {
+            Ok(args[0])
+        }
+        let mut vm = VM::new();
+        vm.define_native("add", 2, add);
+        vm.define_native("shout", 1, shout);
+        vm.define_native("id", 1, id);
+        let source = "var sum = add(1, 2); var t = clock(); var s = shout(\"hi\" + \"!\");
+                      class A { m() { return 7; } } var a = A(); var same = id(a) == a; var m = id(a).m();";
+        assert!(vm.interpret(source).is_ok());
+        assert_eq!(vm.get_global("sum"), Some(HostValue::Number(3.0)));
+        assert!(matches!(vm.get_global("t"), Some(HostValue::Number(_))));
+        assert_eq!(vm.get_global("s"), Some(HostValue::from("hi!!")));
+        assert_eq!(vm.get_global("same"), Some(HostValue::Boolean(true)));
+        assert_eq!(vm.get_global("m"), Some(HostValue::Number(7.0)));
+        assert!(vm.interpret("add(1, nil);").is_err());
+        assert!(vm.interpret("add(1);").is_err());
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    fn natives_cannot_interpret_reentrantly() {
+        fn nested(vm: &mut VM, _args: &[values::Value]) -> Result<values::Value, RuntimeError> {
+            Ok(values::Value::from(vm.interpret("var inner = true;").is_err()))
+        }
+        let mut vm = VM::new();
+        vm.define_native("nested", 0, nested);
+        assert!(vm.interpret("var rejected = nested(); var after = 1;").is_ok());
+        assert_eq!(vm.get_global("rejected"), Some(HostValue::Boolean(true)));
+        assert_eq!(vm.get_global("inner"), None);
+        assert_eq!(vm.get_global("after"), Some(HostValue::Number(1.0)));
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    fn globals_round_trip_through_the_embedding_api() {
+        let mut vm = VM::new();
+        assert!(vm.set_global("greeting", "hello").is_ok());
+        assert!(vm.set_global("loud", true).is_ok());
+        assert!(vm.interpret_named("embed.lox", "var out = greeting + \" world\"; if (loud) out = out + \"!\"; fun f() {}").is_ok());
+        assert_eq!(vm.source_name(), "embed.lox");
+        let out = vm.get_global("out");
+        assert!(vm.interpret("out = nil; var junk = \"a\" + \"b\";").is_ok());
+        assert_eq!(out, Some(HostValue::from("hello world!")));
+        let f = vm.get_global("f");
+        assert_eq!(f, Some(HostValue::Object(String::from("<fn f>"))));
+        assert!(vm.set_global("g", f.unwrap()).is_err());
+        assert_eq!(vm.get_global("g"), None);
+        assert_eq!(vm.get_global("out"), Some(HostValue::Nil));
+        assert!(vm.get_global("missing").is_none());
+        assert!(!vm.strings.contains_key("missing"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn errors_carry_location_and_call_stack() {
+        let mut vm = VM::new();
+        let Err(InterpretError::CompileError(errors)) = vm.interpret_named("bad.lox", "var a = 1;\nprint a +;") else {
+            panic!("expected a compile error");
+        };
+        let error = &errors[0];
+        assert_eq!((error.message(), error.line(), error.location()), ("Expect expression.", 2, " at ';'"));
+        assert_eq!(error.source_name(), "bad.lox");
+        let source = "fun inner() { return -nil; }\nfun outer() {\n  inner();\n}\nouter();";
+        let Err(InterpretError::RuntimeError(error)) = vm.interpret_named("run.lox", source) else {
+            panic!("expected a runtime error");
+        };
+        assert_eq!(error.message(), "Operand must be a number.");
+        assert_eq!(error.line(), 1);
+        let expected = [TraceFrame::new(Some("inner"), 1), TraceFrame::new(Some("outer"), 3), TraceFrame::new(None, 5)];
+        assert_eq!(error.trace(), expected);
+        let rendered = error.to_string();
+        assert_eq!(rendered, "Operand must be a number.\n[line 1] in inner()\n[line 3] in outer()\n[line 5] in script");
+        let wrapped = InterpretError::from(error);
+        assert_eq!(wrapped.to_string(), rendered);
+        assert!(std::error::Error::source(&wrapped).is_none());
+    }
+
+    #[test]
+    fn print_writes_to_the_configured_output() {
+        let mut vm = VM::new();
+        let output = SharedBuffer::default();
+        let diagnostics = SharedBuffer::default();
+        vm.set_output(output.clone());
+        vm.set_diagnostics(diagnostics.clone());
+        let source = "class A {} fun f() {} print 1; print \"two\"; print nil; print A(); print f; print clock;";
+        assert!(vm.interpret(source).is_ok());
+        assert_eq!(output.contents(), "1\ntwo\nnil\nA instance\n<fn f>\n<native fn>\n");
+        if !cfg!(any(feature = "traceExecution", feature = "dumpChunk", feature = "logGc")) {
+            assert!(diagnostics.contents().is_empty());
+        }
+    }
+}
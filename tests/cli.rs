@@ -0,0 +1,79 @@
+//! Checks the `tokenize` and `parse` subcommands of the `rlox` binary.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn run_on(name: &str, command: &str, source: &str) -> Output {
+    let path: PathBuf = std::env::temp_dir().join(format!("rlox-cli-{}-{}.lox", std::process::id(), name));
+    fs::write(&path, source).expect("Failed to write test script");
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox")).arg(command).arg(&path).output().expect("Failed to run rlox");
+    let _ = fs::remove_file(&path);
+    output
+}
+
+#[test]
+fn tokenize_prints_one_token_per_line() {
+    let output = run_on("tokenize", "tokenize", "var café = 1.5;\nprint café;\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines, [
+        "   1:1    VAR            var",
+        "   1:5    IDENTIFIER     café",
+        "   1:10   EQUAL          =",
+        "   1:12   NUMBER         1.5",
+        "   1:15   SEMI_COLON     ;",
+        "   2:1    PRINT          print",
+        "   2:7    IDENTIFIER     café",
+        "   2:11   SEMI_COLON     ;",
+        "   3:1    <EOF>",
+    ]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn tokenize_fails_on_scanning_errors() {
+    let output = run_on("tokenize-error", "tokenize", "var a = @;");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("   1:9    <ERROR>        Unexpected character."));
+    assert_eq!(output.status.code(), Some(65));
+}
+
+#[test]
+fn parse_disassembles_nested_functions_without_running() {
+    let output = run_on("parse", "parse", "fun f() { return 1; }\nprint f();\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let headers: Vec<&str> = stdout.lines().filter(|line| line.starts_with("==")).collect();
+    assert_eq!(headers, ["== f ==", "== script =="]);
+    assert!(stdout.contains("OP_CONSTANT             0 '1'"));
+    assert!(!stdout.lines().any(|line| line == "1"), "parse must not run the script");
+    assert!(output.status.success());
+}
+
+#[test]
+fn parse_reports_compile_errors() {
+    let output = run_on("parse-error", "parse", "print 1 +;");
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error at ';': Expect expression."));
+    assert_eq!(output.status.code(), Some(65));
+}
+
+#[test]
+fn subcommands_without_a_file_print_usage() {
+    for command in ["tokenize", "parse"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_rlox")).arg(command).output().expect("Failed to run rlox");
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Usage:"));
+        assert_eq!(output.status.code(), Some(1));
+    }
+}
+
+#[test]
+fn unreadable_files_are_reported_by_path() {
+    let path = std::env::temp_dir().join("rlox-cli-missing.lox");
+    for args in [vec!["tokenize"], vec!["parse"], vec![]] {
+        let output = Command::new(env!("CARGO_BIN_EXE_rlox")).args(args).arg(&path).output().expect("Failed to run rlox");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(&format!("Could not read file \"{}\"", path.display())), "got {:?}", stderr);
+        assert_eq!(output.status.code(), Some(74));
+    }
+}
@@ -0,0 +1,164 @@
+//! Runs every `.lox` file under `tests/lox/` through the `rlox` binary and
+//! checks it against the annotations in the file, using the conventions of
+//! the Crafting Interpreters test suite:
+//!
+//! - `// expect: text` expects `text` as the next line of output.
+//! - `// expect runtime error: message` expects the script to fail with
+//!   `message` on that line.
+//! - `// Error at 'x': message` expects a compile error on that line, and
+//!   `// [line N] Error ...` (or `// [c line N] Error ...`) one on line `N`.
+//! - `// nontest` skips the file.
+//!
+//! Results are summarized per top-level directory, so a chapter of the
+//! official corpus can be dropped in and tracked on its own.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const COMPILE_ERROR_EXIT: i32 = 65;
+const RUNTIME_ERROR_EXIT: i32 = 70;
+/// The debugging features log to stderr, so error output is only compared
+/// without them; exit codes and stdout are checked either way.
+const CHECK_STDERR: bool = !cfg!(any(feature = "traceExecution", feature = "dumpChunk", feature = "logGc"));
+
+#[derive(Default)]
+struct Expectations {
+    output: Vec<String>,
+    compile_errors: Vec<String>,
+    runtime_error: Option<(String, usize)>,
+}
+
+fn collect_scripts(dir: &Path, scripts: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("Failed to read test directory")
+        .map(|entry| entry.expect("Failed to read directory entry").path())
+        .collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_scripts(&path, scripts);
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            scripts.push(path);
+        }
+    }
+}
+
+/// Returns the text after `marker` if the line has that annotation. The
+/// marker is searched for as a whole, so a `//` inside a string literal
+/// earlier on the line doesn't hide it.
+fn annotation<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    line.find(marker).map(|pos| line[pos + marker.len()..].trim_end())
+}
+
+fn parse_expectations(source: &str) -> Option<Expectations> {
+    let mut expectations = Expectations::default();
+    for (idx, line) in source.lines().enumerate() {
+        let line_number = idx + 1;
+        if line.contains("// nontest") {
+            return None;
+        }
+        if let Some(text) = annotation(line, "// expect: ") {
+            expectations.output.push(text.to_string());
+        } else if let Some(message) = annotation(line, "// expect runtime error: ") {
+            expectations.runtime_error = Some((message.to_string(), line_number));
+        } else if let Some(rest) = annotation(line, "// [line ").or_else(|| annotation(line, "// [c line ")) {
+            expectations.compile_errors.push(format!("[line {}", rest));
+        } else if let Some(rest) = annotation(line, "// Error") {
+            expectations.compile_errors.push(format!("[line {}] Error{}", line_number, rest));
+        }
+    }
+    Some(expectations)
+}
+
+/// Turns `[line 3:7] Error at 'x': ...` into `[line 3] Error at 'x': ...`,
+/// dropping the column the reference implementation does not print.
+fn normalize_compile_error(line: &str) -> Option<String> {
+    let rest = line.strip_prefix("[line ")?;
+    let (location, message) = rest.split_once("] ")?;
+    let line_number = location.split(':').next()?;
+    message.starts_with("Error").then(|| format!("[line {}] {}", line_number, message))
+}
+
+fn check_script(binary: &Path, path: &Path) -> Result<(), Vec<String>> {
+    let source = fs::read_to_string(path).expect("Failed to read test script");
+    let Some(expected) = parse_expectations(&source) else {
+        return Ok(());
+    };
+    let output = Command::new(binary).arg(path).output().expect("Failed to run rlox");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let exit_code = output.status.code().unwrap_or(-1);
+    let mut failures = vec![];
+
+    let actual: Vec<&str> = stdout.lines().collect();
+    if actual != expected.output {
+        failures.push(format!("expected output {:?}, got {:?}", expected.output, actual));
+    }
+
+    if let Some((message, line)) = &expected.runtime_error {
+        let mut lines = stderr.lines();
+        if CHECK_STDERR && lines.next() != Some(message.as_str()) {
+            failures.push(format!("expected runtime error {:?}, got {:?}", message, stderr));
+        }
+        let location = format!("[line {}]", line);
+        if CHECK_STDERR && !lines.next().is_some_and(|trace| trace.starts_with(&location)) {
+            failures.push(format!("expected runtime error on line {}, got {:?}", line, stderr));
+        }
+        if exit_code != RUNTIME_ERROR_EXIT {
+            failures.push(format!("expected exit code {}, got {}", RUNTIME_ERROR_EXIT, exit_code));
+        }
+    } else if !expected.compile_errors.is_empty() {
+        let actual: Vec<String> = stderr.lines().filter_map(normalize_compile_error).collect();
+        if CHECK_STDERR && actual != expected.compile_errors {
+            failures.push(format!("expected compile errors {:?}, got {:?}", expected.compile_errors, actual));
+        }
+        if exit_code != COMPILE_ERROR_EXIT {
+            failures.push(format!("expected exit code {}, got {}", COMPILE_ERROR_EXIT, exit_code));
+        }
+    } else {
+        if CHECK_STDERR && !stderr.is_empty() {
+            failures.push(format!("unexpected error output {:?}", stderr));
+        }
+        if exit_code != 0 {
+            failures.push(format!("expected exit code 0, got {}", exit_code));
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
+
+#[test]
+fn lox_conformance() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("lox");
+    let binary = Path::new(env!("CARGO_BIN_EXE_rlox"));
+    let mut scripts = vec![];
+    collect_scripts(&root, &mut scripts);
+
+    let mut chapters: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    let mut failures = vec![];
+    for path in &scripts {
+        let relative = path.strip_prefix(&root).expect("Script outside the test tree");
+        let chapter = match relative.components().count() {
+            1 => String::from("."),
+            _ => relative.components().next().map_or(String::new(), |c| c.as_os_str().to_string_lossy().into_owned()),
+        };
+        let counts = chapters.entry(chapter).or_default();
+        counts.1 += 1;
+        match check_script(binary, path) {
+            Ok(()) => counts.0 += 1,
+            Err(reasons) => failures.push(format!("{}:\n    {}", relative.display(), reasons.join("\n    "))),
+        }
+    }
+
+    for (chapter, (passed, total)) in &chapters {
+        println!("{:<24} {:>4} / {:<4}", chapter, passed, total);
+    }
+    assert!(!scripts.is_empty(), "No test scripts found in {}", root.display());
+    assert!(failures.is_empty(), "{} of {} scripts failed:\n{}", failures.len(), scripts.len(), failures.join("\n"));
+}